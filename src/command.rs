@@ -8,6 +8,7 @@ use color_eyre::eyre;
 use color_eyre::eyre::eyre;
 use color_eyre::eyre::Context;
 
+use crate::ctrlc;
 use crate::error::TopgradeError;
 
 use tracing::debug;
@@ -159,7 +160,8 @@ impl CommandExt for Command {
         let command = log(self);
 
         // This is where we implement `output_checked`, which is what we prefer to use instead of
-        // `output`, so we allow `Command::output` here.
+        // `output`, so we allow `Command::output` here. It keeps the stdio set by the caller, but
+        // the command isn't tracked, so the step timeout can't stop it.
         #[allow(clippy::disallowed_methods)]
         let output = self
             .output()
@@ -195,9 +197,14 @@ impl CommandExt for Command {
         let message = format!("Failed to execute `{command}`");
 
         // This is where we implement `status_checked`, which is what we prefer to use instead of
-        // `status`, so we allow `Command::status` here.
+        // `status`. It spawns the command to keep track of it for `misc.interrupt_mode`.
+        ctrlc::prepare_child(self);
         #[allow(clippy::disallowed_methods)]
-        let status = self.status().with_context(|| message.clone())?;
+        let mut child = self.spawn().with_context(|| message.clone())?;
+        ctrlc::set_running_child(Some(child.id()));
+        let status = ctrlc::wait_child(&mut child);
+        ctrlc::set_running_child(None);
+        let status = status.with_context(|| message.clone())?;
 
         if succeeded(status).is_ok() {
             Ok(())
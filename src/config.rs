@@ -1,10 +1,11 @@
 #![allow(dead_code)]
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{write, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
 use std::{env, fmt, fs};
 
 use clap::{Parser, ValueEnum};
@@ -16,7 +17,7 @@ use merge::Merge;
 use regex::Regex;
 use regex_split::RegexSplit;
 use rust_i18n::t;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use strum::{EnumIter, EnumString, IntoEnumIterator, VariantNames};
 use which_crate::which;
 
@@ -46,7 +47,7 @@ macro_rules! str_value {
 
 pub type Commands = BTreeMap<String, String>;
 
-#[derive(ValueEnum, EnumString, VariantNames, Debug, Clone, PartialEq, Eq, Deserialize, EnumIter, Copy)]
+#[derive(ValueEnum, EnumString, VariantNames, Debug, Clone, PartialEq, Eq, Hash, Deserialize, EnumIter, Copy)]
 #[clap(rename_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
@@ -70,6 +71,7 @@ pub enum Step {
     Chocolatey,
     Choosenim,
     ClamAvDb,
+    CommandLineTools,
     Composer,
     Conda,
     ConfigUpdate,
@@ -123,6 +125,7 @@ pub enum Step {
     Pip3,
     PipReview,
     PipReviewLocal,
+    PipVirtualenvs,
     Pipupgrade,
     Pipx,
     Pixi,
@@ -174,6 +177,88 @@ pub enum Step {
     Zvm,
 }
 
+const ALL_PLATFORMS: &[&str] = &[
+    "linux",
+    "macos",
+    "windows",
+    "freebsd",
+    "openbsd",
+    "dragonfly",
+    "android",
+];
+const UNIX_PLATFORMS: &[&str] = &["linux", "macos", "freebsd", "openbsd", "dragonfly", "android"];
+
+/// A step as listed by `--list-steps-json`.
+#[derive(Serialize)]
+pub struct StepInfo {
+    name: &'static str,
+    platforms: &'static [&'static str],
+    category: &'static str,
+    default_enabled: bool,
+}
+
+impl Step {
+    /// The operating systems (as in `target_os`) this step runs on.
+    pub fn platforms(self) -> &'static [&'static str] {
+        use Step::*;
+        match self {
+            Chocolatey | MicrosoftStore | Scoop | Winget | Wsl | WslUpdate => &["windows"],
+            AM | AppMan | AutoCpufreq | ConfigUpdate | DebGet | Distrobox | DkpPacman | Firmware | Flatpak | Lure
+            | Pacdef | Pacstall | Protonup | Restarts | Snap | Toolbx | Waydroid => &["linux"],
+            BrewCask | CommandLineTools | Macports | Mas | Sparkle | Xcodes => &["macos"],
+            BrewFormula => &["linux", "macos"],
+            GnomeShellExtensions => &["linux", "freebsd", "openbsd", "dragonfly"],
+            Audit => &["freebsd", "dragonfly"],
+            Pkg => &["freebsd", "openbsd", "dragonfly", "android"],
+            System => &["linux", "macos", "windows", "freebsd", "openbsd"],
+            Atom => &["linux", "macos", "windows", "android"],
+            Asdf | BunPackages | Guix | HomeManager | Maza | Mise | Nix | Pearl | Pkgin | Pyenv | Rcm | Sdkman
+            | Shell | Tldr | Tmux | Yadm => UNIX_PLATFORMS,
+            _ => ALL_PLATFORMS,
+        }
+    }
+
+    /// A rough grouping of the step, for building step pickers.
+    pub fn category(self) -> &'static str {
+        use Step::*;
+        match self {
+            AM | AppMan | Audit | AutoCpufreq | BrewCask | BrewFormula | Chocolatey | CommandLineTools
+            | ConfigUpdate | DebGet | DkpPacman | Firmware | Flatpak | Guix | HomeManager | Lure | Macports | Mas
+            | MicrosoftStore | Nix | Pacdef | Pacstall | Pkg | Pkgin | Restarts | Scoop | Snap | Sparkle | System
+            | Winget | Xcodes => "system",
+            Containers | Distrobox | Toolbx | Vagrant | Waydroid | Wsl | WslUpdate => "containers",
+            Atom | Bob | Emacs | Helix | Kakoune | Micro | Vim | Vscode | Vscodium => "editors",
+            Chezmoi | Maza | Pearl | Rcm | Shell | Tldr | Tmux | Yadm => "shell",
+            Fossil | GitRepos | Myrepos => "vcs",
+            CustomCommands | Remotes | SelfUpdate => "topgrade",
+            _ => "packages",
+        }
+    }
+
+    /// Whether the step can only download updates, see `misc.download_only`.
+    pub fn supports_download_only(self) -> bool {
+        cfg!(target_os = "linux") && matches!(self, Step::System)
+    }
+
+    /// Whether the step runs without being enabled in the configuration file.
+    pub fn default_enabled(self) -> bool {
+        !matches!(self, Step::PipReview | Step::PipReviewLocal | Step::Pipupgrade)
+    }
+
+    /// All the steps with their metadata, for `--list-steps-json`.
+    pub fn list() -> Vec<StepInfo> {
+        Step::iter()
+            .zip(Step::VARIANTS)
+            .map(|(step, name)| StepInfo {
+                name,
+                platforms: step.platforms(),
+                category: step.category(),
+                default_enabled: step.default_enabled(),
+            })
+            .collect()
+    }
+}
+
 #[derive(Deserialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Include {
@@ -187,6 +272,56 @@ pub struct Containers {
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
     ignored_containers: Option<Vec<String>>,
     runtime: Option<ContainerRuntime>,
+
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    compose_files: Option<Vec<String>>,
+    compose_up: Option<bool>,
+}
+
+/// A git repository to pull, either a path (glob) or a table with more options.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum GitRepo {
+    Path(String),
+    Detailed(GitRepoDetails),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GitRepoDetails {
+    path: String,
+    expected_branch: Option<String>,
+    command: Option<String>,
+}
+
+impl GitRepo {
+    pub fn path(&self) -> &str {
+        match self {
+            GitRepo::Path(path) | GitRepo::Detailed(GitRepoDetails { path, .. }) => path,
+        }
+    }
+
+    fn path_mut(&mut self) -> &mut String {
+        match self {
+            GitRepo::Path(path) | GitRepo::Detailed(GitRepoDetails { path, .. }) => path,
+        }
+    }
+
+    /// The branch the repository has to be on to be pulled.
+    pub fn expected_branch(&self) -> Option<&str> {
+        match self {
+            GitRepo::Path(_) => None,
+            GitRepo::Detailed(details) => details.expected_branch.as_deref(),
+        }
+    }
+
+    /// The command pulling the repository instead of `git pull`.
+    pub fn command(&self) -> Option<&str> {
+        match self {
+            GitRepo::Path(_) => None,
+            GitRepo::Detailed(details) => details.command.as_deref(),
+        }
+    }
 }
 
 #[derive(Deserialize, Default, Debug, Merge)]
@@ -198,9 +333,24 @@ pub struct Git {
     arguments: Option<String>,
 
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
-    repos: Option<Vec<String>>,
+    repos: Option<Vec<GitRepo>>,
+
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    search_roots: Option<Vec<String>>,
+
+    search_depth: Option<usize>,
 
     pull_predefined: Option<bool>,
+
+    allow_prompt: Option<bool>,
+
+    lfs: Option<bool>,
+
+    auto_stash: Option<bool>,
+
+    fetch_all_remotes: Option<bool>,
+
+    verify_signatures: Option<bool>,
 }
 
 #[derive(Deserialize, Default, Debug, Merge)]
@@ -221,6 +371,14 @@ pub struct Windows {
     open_remotes_in_new_terminal: Option<bool>,
     wsl_update_pre_release: Option<bool>,
     wsl_update_use_web_download: Option<bool>,
+    winget_export_path: Option<String>,
+    winget_silent: Option<bool>,
+    winget_configure_file: Option<String>,
+    scoop_global: Option<bool>,
+    choco_ignore_checksums: Option<bool>,
+
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    choco_exclude: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Default, Debug, Merge)]
@@ -231,6 +389,12 @@ pub struct Python {
     enable_pipupgrade: Option<bool>,
     pipupgrade_arguments: Option<String>,
     poetry_force_self_update: Option<bool>,
+
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    interpreters: Option<Vec<String>>,
+
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    virtualenvs: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Default, Debug, Merge)]
@@ -276,6 +440,66 @@ pub struct Firmware {
 #[allow(clippy::upper_case_acronyms)]
 pub struct Flatpak {
     use_sudo: Option<bool>,
+    assume_yes: Option<bool>,
+    scope: Option<FlatpakScope>,
+    retry: Option<u32>,
+    repair: Option<bool>,
+
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    keep_runtimes: Option<Vec<String>>,
+
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    apps: Option<Vec<String>>,
+
+    confirm_removals: Option<bool>,
+}
+
+#[derive(Deserialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Snap {
+    remove_disabled: Option<bool>,
+    confirm_removals: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryFilter {
+    /// Print all the results
+    #[default]
+    All,
+    /// Hide the steps that had nothing to do
+    Changed,
+    /// Only print the failed and skipped steps
+    Problems,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InterruptMode {
+    /// Let the running step finish, then skip the remaining steps
+    #[default]
+    Graceful,
+    /// Stop the running command and ask whether to retry the step
+    Immediate,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlatpakScope {
+    User,
+    System,
+    #[default]
+    Both,
+}
+
+impl FlatpakScope {
+    pub fn includes_user(self) -> bool {
+        matches!(self, FlatpakScope::User | FlatpakScope::Both)
+    }
+
+    pub fn includes_system(self) -> bool {
+        matches!(self, FlatpakScope::System | FlatpakScope::Both)
+    }
 }
 
 #[derive(Deserialize, Default, Debug, Merge)]
@@ -284,8 +508,20 @@ pub struct Brew {
     greedy_cask: Option<bool>,
     greedy_latest: Option<bool>,
     greedy_auto_updates: Option<bool>,
+    cask_no_quarantine: Option<bool>,
     autoremove: Option<bool>,
+    autoremove_dry_run: Option<bool>,
     fetch_head: Option<bool>,
+    force_link: Option<bool>,
+    restart_services: Option<bool>,
+    quiet: Option<bool>,
+    verbose: Option<bool>,
+    no_analytics: Option<bool>,
+    git_remote: Option<String>,
+    core_git_remote: Option<String>,
+
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    services: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize, Clone, Copy)]
@@ -318,6 +554,22 @@ impl fmt::Display for ContainerRuntime {
     }
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PowershellModuleScope {
+    CurrentUser,
+    AllUsers,
+}
+
+impl fmt::Display for PowershellModuleScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowershellModuleScope::CurrentUser => write!(f, "CurrentUser"),
+            PowershellModuleScope::AllUsers => write!(f, "AllUsers"),
+        }
+    }
+}
+
 #[derive(Deserialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Linux {
@@ -331,6 +583,7 @@ pub struct Linux {
     aura_pacman_arguments: Option<String>,
     arch_package_manager: Option<ArchPackageManager>,
     show_arch_news: Option<bool>,
+    pacnew_scan: Option<bool>,
 
     #[merge(strategy = crate::utils::merge_strategies::string_append_opt)]
     garuda_update_arguments: Option<String>,
@@ -358,9 +611,22 @@ pub struct Linux {
 
     enable_tlmgr: Option<bool>,
     redhat_distro_sync: Option<bool>,
+    dnf_allowerasing: Option<bool>,
+    dnf_best: Option<bool>,
     suse_dup: Option<bool>,
     rpm_ostree: Option<bool>,
     bootc: Option<bool>,
+    apt_unhold: Option<bool>,
+    security_only: Option<bool>,
+    autoremove_dry_run: Option<bool>,
+    apt_autoremove_purge: Option<bool>,
+    apk_autofix: Option<bool>,
+    dpkg_auto_configure: Option<bool>,
+    show_changelogs: Option<bool>,
+    show_update_repos: Option<bool>,
+    country: Option<String>,
+    lock_retry: Option<u32>,
+    gnome_extensions_use_dbus: Option<bool>,
 
     #[merge(strategy = crate::utils::merge_strategies::string_append_opt)]
     emerge_sync_flags: Option<String>,
@@ -397,11 +663,15 @@ pub struct Misc {
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
     ignore_failures: Option<Vec<Step>>,
 
+    ignore_failure_patterns: Option<HashMap<Step, String>>,
+
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
     remote_topgrades: Option<Vec<String>>,
 
     remote_topgrade_path: Option<String>,
 
+    remote_dry_run_always: Option<bool>,
+
     #[merge(strategy = crate::utils::merge_strategies::string_append_opt)]
     ssh_arguments: Option<String>,
 
@@ -416,10 +686,20 @@ pub struct Misc {
 
     no_retry: Option<bool>,
 
+    quiet: Option<bool>,
+
+    fail_fast: Option<bool>,
+
+    default_step_timeout: Option<String>,
+
+    download_only: Option<bool>,
+
     run_in_tmux: Option<bool>,
 
     tmux_session_mode: Option<TmuxSessionMode>,
 
+    tmux_session_name: Option<String>,
+
     cleanup: Option<bool>,
 
     notify_each_step: Option<bool>,
@@ -428,6 +708,26 @@ pub struct Misc {
 
     bashit_branch: Option<String>,
 
+    env_file: Option<String>,
+
+    post_step_command: Option<String>,
+
+    max_captured_output: Option<usize>,
+
+    log_dir: Option<String>,
+
+    proxy: Option<String>,
+
+    bandwidth_limit: Option<u32>,
+
+    hooks_dir: Option<String>,
+
+    summary_filter: Option<SummaryFilter>,
+
+    interrupt_mode: Option<InterruptMode>,
+
+    custom_command_shell: Option<String>,
+
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
     only: Option<Vec<Step>>,
 
@@ -447,6 +747,7 @@ pub enum TmuxSessionMode {
 pub struct TmuxConfig {
     pub args: Vec<String>,
     pub session_mode: TmuxSessionMode,
+    pub session_name: Option<String>,
 }
 
 #[derive(Deserialize, Default, Debug, Merge)]
@@ -461,6 +762,106 @@ pub struct JuliaConfig {
     startup_file: Option<bool>,
 }
 
+#[derive(Deserialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Cargo {
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    projects: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Sdkman {
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    candidates: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Rustup {
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    toolchains: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Powershell {
+    scope: Option<PowershellModuleScope>,
+
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    skip: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Gcloud {
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    components: Option<Vec<String>>,
+
+    include_preview: Option<bool>,
+}
+
+#[derive(Deserialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct FreeBSD {
+    #[merge(strategy = crate::utils::merge_strategies::string_append_opt)]
+    pkg_args: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct NixConfig {
+    update_flake_inputs: Option<bool>,
+    flake_dir: Option<String>,
+    gc_keep_days: Option<u32>,
+    optimise: Option<bool>,
+    show_diff: Option<bool>,
+}
+
+#[derive(Deserialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct MacOS {
+    mas_force: Option<bool>,
+    accept_all_updates: Option<bool>,
+
+    #[merge(strategy = crate::utils::merge_strategies::string_append_opt)]
+    macports_args: Option<String>,
+
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    sparkle_apps: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct AsdfConfig {
+    install_latest: Option<bool>,
+
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    tools: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct CondaConfig {
+    all_envs: Option<bool>,
+
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    envs: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Tex {
+    user_mode: Option<bool>,
+}
+
+#[derive(Deserialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct GoConfig {
+    update_toolchain: Option<bool>,
+    clean_cache: Option<bool>,
+}
+
 #[derive(Deserialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 /// Configuration file
@@ -522,6 +923,9 @@ pub struct ConfigFile {
     #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
     flatpak: Option<Flatpak>,
 
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    snap: Option<Snap>,
+
     #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
     distrobox: Option<Distrobox>,
 
@@ -530,6 +934,42 @@ pub struct ConfigFile {
 
     #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
     julia: Option<JuliaConfig>,
+
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    nix: Option<NixConfig>,
+
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    freebsd: Option<FreeBSD>,
+
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    gcloud: Option<Gcloud>,
+
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    cargo: Option<Cargo>,
+
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    sdkman: Option<Sdkman>,
+
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    rustup: Option<Rustup>,
+
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    powershell: Option<Powershell>,
+
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    go: Option<GoConfig>,
+
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    tex: Option<Tex>,
+
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    conda: Option<CondaConfig>,
+
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    macos: Option<MacOS>,
+
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    asdf: Option<AsdfConfig>,
 }
 
 fn config_directory() -> PathBuf {
@@ -540,6 +980,60 @@ fn config_directory() -> PathBuf {
     return crate::WINDOWS_DIRS.config_dir();
 }
 
+/// Parse the `KEY=VALUE` lines of a dotenv-style file.
+///
+/// Empty lines and lines starting with `#` are ignored, as is a leading `export `.
+/// Values may be wrapped in single or double quotes.
+pub fn parse_env_file(contents: &str) -> Vec<(String, String)> {
+    let mut variables = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            debug!("Ignoring invalid line in env file: {line}");
+            continue;
+        };
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| value.strip_prefix(*quote).and_then(|v| v.strip_suffix(*quote)))
+            .unwrap_or(value);
+        variables.push((key.trim().to_string(), value.to_string()));
+    }
+    variables
+}
+
+/// Parse a duration like `30s`, `5m` or `1h`. A number without a unit is in seconds.
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let duration = duration.trim();
+    let (number, unit) = duration.split_at(duration.find(|c: char| !c.is_ascii_digit()).unwrap_or(duration.len()));
+    let seconds = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return Err(format!("Invalid duration unit in `{duration}`, expected s, m or h")),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(seconds))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("Invalid duration `{duration}`"))
+}
+
+/// Check that the URL of the option `name` isn't empty, logging an error otherwise.
+fn non_empty_url(url: &str, name: &str) -> bool {
+    if url.trim().is_empty() {
+        error!("Ignoring {name}, the URL is empty");
+        false
+    } else {
+        true
+    }
+}
+
 /// The only purpose of this struct is to deserialize only the `include` field of the config file.
 #[derive(Deserialize, Default, Debug)]
 struct ConfigFileIncludeOnly {
@@ -696,8 +1190,8 @@ impl ConfigFile {
             }
         }
 
-        if let Some(paths) = result.git.as_mut().and_then(|git| git.repos.as_mut()) {
-            for path in paths.iter_mut() {
+        if let Some(repos) = result.git.as_mut().and_then(|git| git.repos.as_mut()) {
+            for path in repos.iter_mut().map(GitRepo::path_mut) {
                 let expanded = shellexpand::tilde::<&str>(&path.as_ref()).into_owned();
                 debug!(
                     "{}",
@@ -769,6 +1263,22 @@ pub struct CommandLineArgs {
     #[arg(long = "no-retry")]
     no_retry: bool,
 
+    /// Only print the output of the steps that fail or are skipped
+    #[arg(long = "quiet")]
+    quiet: bool,
+
+    /// Stop running steps after the first failure
+    #[arg(long = "fail-fast")]
+    fail_fast: bool,
+
+    /// Fail the steps that run longer than this, e.g. `30s`, `5m` or `1h`
+    #[arg(long = "step-timeout", value_name = "DURATION", value_parser = parse_duration)]
+    step_timeout: Option<Duration>,
+
+    /// Run even if another Topgrade instance seems to be running
+    #[arg(long = "force")]
+    force: bool,
+
     /// Do not perform upgrades for the given steps
     #[arg(long = "disable", value_name = "STEP", value_enum, num_args = 1..)]
     disable: Vec<Step>,
@@ -785,6 +1295,10 @@ pub struct CommandLineArgs {
     #[arg(long = "env", value_name = "NAME=VALUE", num_args = 1..)]
     env: Vec<String>,
 
+    /// Load environment variables from a dotenv-style file
+    #[arg(long = "env-file", value_name = "PATH")]
+    env_file: Option<PathBuf>,
+
     /// Output debug logs. Alias for `--log-filter debug`.
     #[arg(short = 'v', long = "verbose")]
     pub verbose: bool,
@@ -823,6 +1337,14 @@ pub struct CommandLineArgs {
     #[arg(long = "show-skipped")]
     show_skipped: bool,
 
+    /// List the skipped steps by reason at the end of the run, with hints on how to enable them
+    #[arg(long = "explain-skips")]
+    explain_skips: bool,
+
+    /// When a step fails, write its results, the configuration and the OS details to this file, for bug reports
+    #[arg(long = "dump-report-on-failure", value_name = "PATH")]
+    dump_report_on_failure: Option<PathBuf>,
+
     /// Tracing filter directives.
     ///
     /// See: https://docs.rs/tracing-subscriber/latest/tracing_subscriber/struct.EnvFilter.html
@@ -837,6 +1359,14 @@ pub struct CommandLineArgs {
     #[arg(long, hide = true)]
     pub gen_manpage: bool,
 
+    /// Print the available steps as JSON and exit
+    #[arg(long, hide = true)]
+    pub list_steps_json: bool,
+
+    /// Print a separator in Topgrade's style and exit, for use in custom scripts
+    #[arg(long, value_name = "TEXT")]
+    pub emit_separator: Option<String>,
+
     /// Don't update Topgrade
     #[arg(long = "no-self-update")]
     pub no_self_update: bool,
@@ -937,10 +1467,34 @@ impl Config {
     }
 
     /// The list of additional git repositories to pull.
-    pub fn git_repos(&self) -> Option<&Vec<String>> {
+    pub fn git_repos(&self) -> Option<&Vec<GitRepo>> {
         self.config_file.git.as_ref().and_then(|git| git.repos.as_ref())
     }
 
+    /// The list of directories to search for git repositories to pull.
+    pub fn git_search_roots(&self) -> Vec<PathBuf> {
+        self.config_file
+            .git
+            .as_ref()
+            .and_then(|git| git.search_roots.as_ref())
+            .map(|roots| {
+                roots
+                    .iter()
+                    .map(|root| PathBuf::from(shellexpand::tilde(root).into_owned()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// How many directories deep to search for git repositories under the search roots.
+    pub fn git_search_depth(&self) -> usize {
+        self.config_file
+            .git
+            .as_ref()
+            .and_then(|git| git.search_depth)
+            .unwrap_or(3)
+    }
+
     /// The list of docker/podman containers to ignore.
     pub fn containers_ignored_tags(&self) -> Option<&Vec<String>> {
         self.config_file
@@ -958,6 +1512,30 @@ impl Config {
             .unwrap_or(ContainerRuntime::Docker) // defaults to a popular choice
     }
 
+    /// Compose files whose images should be pulled
+    pub fn compose_files(&self) -> Vec<PathBuf> {
+        self.config_file
+            .containers
+            .as_ref()
+            .and_then(|containers| containers.compose_files.as_ref())
+            .map(|files| {
+                files
+                    .iter()
+                    .map(|file| PathBuf::from(shellexpand::tilde(file).into_owned()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Recreate the containers of the compose files after pulling their images
+    pub fn compose_up(&self) -> bool {
+        self.config_file
+            .containers
+            .as_ref()
+            .and_then(|containers| containers.compose_up)
+            .unwrap_or(false)
+    }
+
     /// Tell whether the specified step should run.
     ///
     /// If the step appears either in the `--disable` command line argument
@@ -1023,6 +1601,14 @@ impl Config {
             .unwrap_or(TmuxSessionMode::AttachIfNotInSession)
     }
 
+    /// The name of the tmux session to reuse across runs.
+    fn tmux_session_name(&self) -> Option<String> {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.tmux_session_name.clone())
+    }
+
     /// Tell whether we should perform cleanup steps.
     pub fn cleanup(&self) -> bool {
         self.opt.cleanup
@@ -1050,11 +1636,57 @@ impl Config {
                 .unwrap_or(false)
     }
 
-    /// List of remote hosts to run Topgrade in
-    pub fn remote_topgrades(&self) -> Option<&Vec<String>> {
-        self.config_file
-            .misc
-            .as_ref()
+    /// Only print the output of the steps that fail or are skipped, and one line for the others.
+    pub fn quiet(&self) -> bool {
+        self.opt.quiet
+            || self
+                .config_file
+                .misc
+                .as_ref()
+                .and_then(|misc| misc.quiet)
+                .unwrap_or(false)
+    }
+
+    /// Only download updates without installing them.
+    pub fn download_only(&self) -> bool {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.download_only)
+            .unwrap_or(false)
+    }
+
+    /// Ignore the lock held by another running Topgrade.
+    pub fn force(&self) -> bool {
+        self.opt.force
+    }
+
+    /// Tell whether to skip the remaining steps after a step fails.
+    pub fn fail_fast(&self) -> bool {
+        self.opt.fail_fast
+            || self
+                .config_file
+                .misc
+                .as_ref()
+                .and_then(|misc| misc.fail_fast)
+                .unwrap_or(false)
+    }
+
+    /// How long a step can run before it is stopped and marked as failed.
+    pub fn step_timeout(&self) -> Option<Duration> {
+        self.opt.step_timeout.or_else(|| {
+            let timeout = self.config_file.misc.as_ref()?.default_step_timeout.as_ref()?;
+            parse_duration(timeout)
+                .map_err(|e| error!("Invalid default_step_timeout: {e}"))
+                .ok()
+        })
+    }
+
+    /// List of remote hosts to run Topgrade in
+    pub fn remote_topgrades(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .misc
+            .as_ref()
             .and_then(|misc| misc.remote_topgrades.as_ref())
     }
 
@@ -1067,6 +1699,15 @@ impl Config {
             .unwrap_or("topgrade")
     }
 
+    /// Run the remote Topgrades with `--dry-run`, even when not dry running locally
+    pub fn remote_dry_run_always(&self) -> bool {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.remote_dry_run_always)
+            .unwrap_or(false)
+    }
+
     /// Extra SSH arguments
     pub fn ssh_arguments(&self) -> Option<&String> {
         self.config_file
@@ -1085,6 +1726,7 @@ impl Config {
         Ok(TmuxConfig {
             args,
             session_mode: self.tmux_session_mode(),
+            session_name: self.tmux_session_name(),
         })
     }
 
@@ -1147,6 +1789,14 @@ impl Config {
         false
     }
 
+    /// Whether every step answers yes to its prompts, with `--yes` or `misc.assume_yes`
+    pub fn yes_to_all(&self) -> bool {
+        match self.config_file.misc.as_ref().and_then(|misc| misc.assume_yes) {
+            Some(yes) => yes,
+            None => self.opt.yes.as_ref().is_some_and(Vec::is_empty),
+        }
+    }
+
     /// Bash-it branch
     pub fn bashit_branch(&self) -> &str {
         self.config_file
@@ -1156,6 +1806,74 @@ impl Config {
             .unwrap_or("stable")
     }
 
+    /// Which results to print in the summary
+    pub fn summary_filter(&self) -> SummaryFilter {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.summary_filter)
+            .unwrap_or_default()
+    }
+
+    /// What to do on ctrl-c
+    pub fn interrupt_mode(&self) -> InterruptMode {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.interrupt_mode)
+            .unwrap_or_default()
+    }
+
+    /// The maximum number of bytes kept from the captured output of a command
+    pub fn max_captured_output(&self) -> Option<usize> {
+        self.config_file.misc.as_ref().and_then(|misc| misc.max_captured_output)
+    }
+
+    /// The maximum download speed in KiB/s, for the steps able to limit it
+    #[cfg(target_os = "linux")]
+    pub fn bandwidth_limit(&self) -> Option<u32> {
+        self.config_file.misc.as_ref().and_then(|misc| misc.bandwidth_limit)
+    }
+
+    /// A directory whose executable files are run as custom commands
+    pub fn hooks_dir(&self) -> Option<PathBuf> {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.hooks_dir.as_ref())
+            .map(|dir| PathBuf::from(shellexpand::tilde(dir).into_owned()))
+    }
+
+    /// The HTTP(S) proxy to use for the steps
+    pub fn proxy(&self) -> Option<&str> {
+        self.config_file.misc.as_ref().and_then(|misc| misc.proxy.as_deref())
+    }
+
+    /// Where to save the output of every step
+    pub fn log_dir(&self) -> Option<PathBuf> {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.log_dir.as_ref())
+            .map(|dir| PathBuf::from(shellexpand::tilde(dir).into_owned()))
+    }
+
+    /// A command to run after every successful step
+    pub fn post_step_command(&self) -> Option<&str> {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.post_step_command.as_deref())
+    }
+
+    /// The shell used to run custom commands, overriding the detected one
+    pub fn custom_command_shell(&self) -> Option<&str> {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.custom_command_shell.as_deref())
+    }
+
     /// Whether to accept all Windows updates
     pub fn accept_all_windows_updates(&self) -> bool {
         self.config_file
@@ -1165,6 +1883,26 @@ impl Config {
             .unwrap_or(true)
     }
 
+    /// Pass `--ignore-checksums` to `choco upgrade`, if `accept_all_windows_updates` is also set
+    #[cfg(windows)]
+    pub fn choco_ignore_checksums(&self) -> bool {
+        self.config_file
+            .windows
+            .as_ref()
+            .and_then(|windows| windows.choco_ignore_checksums)
+            .unwrap_or(false)
+    }
+
+    /// Packages that `choco upgrade all` should not upgrade
+    #[cfg(windows)]
+    pub fn choco_exclude(&self) -> &[String] {
+        self.config_file
+            .windows
+            .as_ref()
+            .and_then(|windows| windows.choco_exclude.as_deref())
+            .unwrap_or_default()
+    }
+
     /// Whether to self rename the Topgrade executable during the run
     pub fn self_rename(&self) -> bool {
         self.config_file
@@ -1192,6 +1930,43 @@ impl Config {
             .unwrap_or(false)
     }
 
+    /// Where to export the list of winget packages before upgrading
+    pub fn winget_export_path(&self) -> Option<PathBuf> {
+        self.config_file
+            .windows
+            .as_ref()
+            .and_then(|w| w.winget_export_path.as_deref())
+            .map(|path| PathBuf::from(shellexpand::tilde(path).into_owned()))
+    }
+
+    /// The winget configuration file to apply after upgrading
+    pub fn winget_configure_file(&self) -> Option<PathBuf> {
+        self.config_file
+            .windows
+            .as_ref()
+            .and_then(|w| w.winget_configure_file.as_deref())
+            .map(|path| PathBuf::from(shellexpand::tilde(path).into_owned()))
+    }
+
+    /// Upgrade winget packages without prompting
+    #[cfg(windows)]
+    pub fn winget_silent(&self) -> bool {
+        self.config_file
+            .windows
+            .as_ref()
+            .and_then(|w| w.winget_silent)
+            .unwrap_or(false)
+    }
+
+    /// Also update globally installed Scoop apps when running elevated
+    pub fn scoop_global(&self) -> bool {
+        self.config_file
+            .windows
+            .as_ref()
+            .and_then(|w| w.scoop_global)
+            .unwrap_or(true)
+    }
+
     /// Whether Brew cask should be greedy
     pub fn brew_cask_greedy(&self) -> bool {
         self.config_file
@@ -1219,6 +1994,15 @@ impl Config {
             .unwrap_or(false)
     }
 
+    /// Whether Brew casks should be upgraded without the quarantine attribute
+    pub fn brew_cask_no_quarantine(&self) -> bool {
+        self.config_file
+            .brew
+            .as_ref()
+            .and_then(|c| c.cask_no_quarantine)
+            .unwrap_or(false)
+    }
+
     /// Whether Brew should autoremove
     pub fn brew_autoremove(&self) -> bool {
         self.config_file
@@ -1228,6 +2012,15 @@ impl Config {
             .unwrap_or(false)
     }
 
+    /// Whether Brew should only list what `brew autoremove` would remove
+    pub fn brew_autoremove_dry_run(&self) -> bool {
+        self.config_file
+            .brew
+            .as_ref()
+            .and_then(|c| c.autoremove_dry_run)
+            .unwrap_or(false)
+    }
+
     /// Whether Brew should upgrade formulae built from the HEAD branch
     pub fn brew_fetch_head(&self) -> bool {
         self.config_file
@@ -1237,6 +2030,66 @@ impl Config {
             .unwrap_or(false)
     }
 
+    /// Overwrite the conflicting files when `brew upgrade` can't link a formula, and upgrade again
+    pub fn brew_force_link(&self) -> bool {
+        self.config_file
+            .brew
+            .as_ref()
+            .and_then(|c| c.force_link)
+            .unwrap_or(false)
+    }
+
+    /// Pass `--quiet` to `brew update` and `brew upgrade`
+    pub fn brew_quiet(&self) -> bool {
+        self.config_file.brew.as_ref().and_then(|c| c.quiet).unwrap_or(false)
+    }
+
+    /// Pass `--verbose` to `brew update` and `brew upgrade`
+    pub fn brew_verbose(&self) -> bool {
+        self.config_file.brew.as_ref().and_then(|c| c.verbose).unwrap_or(false)
+    }
+
+    /// Set `HOMEBREW_NO_ANALYTICS` for this run
+    pub fn brew_no_analytics(&self) -> bool {
+        self.config_file
+            .brew
+            .as_ref()
+            .and_then(|c| c.no_analytics)
+            .unwrap_or(false)
+    }
+
+    /// The mirror of the Homebrew repository, set as `HOMEBREW_BREW_GIT_REMOTE`
+    pub fn brew_git_remote(&self) -> Option<&str> {
+        self.config_file
+            .brew
+            .as_ref()
+            .and_then(|c| c.git_remote.as_deref())
+            .filter(|url| non_empty_url(url, "brew.git_remote"))
+    }
+
+    /// The mirror of the homebrew/core tap, set as `HOMEBREW_CORE_GIT_REMOTE`
+    pub fn brew_core_git_remote(&self) -> Option<&str> {
+        self.config_file
+            .brew
+            .as_ref()
+            .and_then(|c| c.core_git_remote.as_deref())
+            .filter(|url| non_empty_url(url, "brew.core_git_remote"))
+    }
+
+    /// Whether Brew should restart running services after upgrading formulae
+    pub fn brew_restart_services(&self) -> bool {
+        self.config_file
+            .brew
+            .as_ref()
+            .and_then(|c| c.restart_services)
+            .unwrap_or(false)
+    }
+
+    /// The Brew services to restart, all running services if not set
+    pub fn brew_services(&self) -> Option<&Vec<String>> {
+        self.config_file.brew.as_ref().and_then(|c| c.services.as_ref())
+    }
+
     /// Whether Composer should update itself
     pub fn composer_self_update(&self) -> bool {
         self.config_file
@@ -1310,6 +2163,15 @@ impl Config {
             .unwrap_or(true)
     }
 
+    /// Look for `.pacnew` files on any system with pacman, not only on Arch Linux
+    pub fn pacnew_scan(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|s| s.pacnew_scan)
+            .unwrap_or(false)
+    }
+
     /// Get the package manager of an Arch Linux system
     pub fn arch_package_manager(&self) -> ArchPackageManager {
         self.config_file
@@ -1352,6 +2214,104 @@ impl Config {
             .and_then(|linux| linux.apt_arguments.as_deref())
     }
 
+    /// Unhold packages marked with `apt-mark hold` before upgrading
+    pub fn apt_unhold(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.apt_unhold)
+            .unwrap_or(false)
+    }
+
+    /// Only apply security updates with the system package manager
+    pub fn security_only(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.security_only)
+            .unwrap_or(false)
+    }
+
+    /// List the packages `autoremove` would remove during cleanup instead of removing them
+    pub fn autoremove_dry_run(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.autoremove_dry_run)
+            .unwrap_or(false)
+    }
+
+    /// Also remove the configuration files of the packages removed by `apt autoremove`
+    pub fn apt_autoremove_purge(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.apt_autoremove_purge)
+            .unwrap_or(false)
+    }
+
+    /// Run `apk fix` and retry when `apk upgrade` fails
+    pub fn apk_autofix(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.apk_autofix)
+            .unwrap_or(false)
+    }
+
+    /// Run `dpkg --configure -a` and retry when apt fails because dpkg was interrupted
+    pub fn dpkg_auto_configure(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.dpkg_auto_configure)
+            .unwrap_or(true)
+    }
+
+    /// Show the changelogs of the updates before applying them
+    pub fn show_changelogs(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.show_changelogs)
+            .unwrap_or(false)
+    }
+
+    /// Count the updates of each repository after upgrading with apt or dnf
+    pub fn show_update_repos(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.show_update_repos)
+            .unwrap_or(false)
+    }
+
+    /// The country whose mirrors should be used
+    pub fn linux_country(&self) -> Option<&str> {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.country.as_deref())
+    }
+
+    /// How many times to retry apt and dnf while another process holds their lock
+    pub fn lock_retry(&self) -> u32 {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.lock_retry)
+            .unwrap_or(0)
+    }
+
+    /// Always update GNOME Shell extensions through DBus, even if `gext` is installed
+    pub fn gnome_extensions_use_dbus(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.gnome_extensions_use_dbus)
+            .unwrap_or(false)
+    }
+
     /// Extra dnf arguments
     pub fn dnf_arguments(&self) -> Option<&str> {
         self.config_file
@@ -1442,6 +2402,20 @@ impl Config {
             .unwrap_or(false)
     }
 
+    /// Let dnf remove installed packages to resolve dependency conflicts
+    pub fn dnf_allowerasing(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.dnf_allowerasing)
+            .unwrap_or(false)
+    }
+
+    /// Pass `--best` (true) or `--nobest` (false) to dnf, or keep its own setting when unset
+    pub fn dnf_best(&self) -> Option<bool> {
+        self.config_file.linux.as_ref().and_then(|linux| linux.dnf_best)
+    }
+
     /// Use zypper dist-upgrade (same as distro-sync on RH) instead of update (default: false on SLE/Leap, ignored on Tumbleweed (dup is always ran))
     pub fn suse_dup(&self) -> bool {
         self.config_file
@@ -1479,6 +2453,20 @@ impl Config {
             .unwrap_or(false)
     }
 
+    /// The pattern matching the output of the failures to ignore for this step
+    pub fn ignore_failure_pattern(&self, step: Step) -> Option<Regex> {
+        let pattern = self
+            .config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.ignore_failure_patterns.as_ref())
+            .and_then(|patterns| patterns.get(&step))?;
+
+        Regex::new(pattern)
+            .map_err(|e| error!("Invalid pattern in ignore_failure_patterns for {step:?}: {e}"))
+            .ok()
+    }
+
     pub fn use_predefined_git_repos(&self) -> bool {
         !self.opt.disable_predefined_git_repos
             && self
@@ -1489,10 +2477,66 @@ impl Config {
                 .unwrap_or(true)
     }
 
+    /// Run `git lfs pull` after pulling the repositories using Git LFS
+    pub fn git_lfs(&self) -> bool {
+        self.config_file.git.as_ref().and_then(|git| git.lfs).unwrap_or(false)
+    }
+
+    /// Stash the uncommitted changes of the repositories while pulling them
+    pub fn git_auto_stash(&self) -> bool {
+        self.config_file
+            .git
+            .as_ref()
+            .and_then(|git| git.auto_stash)
+            .unwrap_or(false)
+    }
+
+    /// Fetch all the remotes of the repositories before pulling them
+    pub fn git_fetch_all_remotes(&self) -> bool {
+        self.config_file
+            .git
+            .as_ref()
+            .and_then(|git| git.fetch_all_remotes)
+            .unwrap_or(false)
+    }
+
+    /// Warn when the new HEAD of a pulled repository isn't signed
+    pub fn git_verify_signatures(&self) -> bool {
+        self.config_file
+            .git
+            .as_ref()
+            .and_then(|git| git.verify_signatures)
+            .unwrap_or(false)
+    }
+
+    /// Let git prompt for credentials when pulling
+    pub fn git_allow_prompt(&self) -> bool {
+        self.config_file
+            .git
+            .as_ref()
+            .and_then(|git| git.allow_prompt)
+            .unwrap_or(false)
+    }
+
     pub fn verbose(&self) -> bool {
         self.opt.verbose
     }
 
+    pub fn env_variables(&self) -> &Vec<String> {
+        self.opt.env_variables()
+    }
+
+    /// The dotenv-style file to load environment variables from
+    pub fn env_file(&self) -> Option<PathBuf> {
+        self.opt.env_file.clone().or_else(|| {
+            self.config_file
+                .misc
+                .as_ref()
+                .and_then(|misc| misc.env_file.as_deref())
+                .map(|path| PathBuf::from(shellexpand::tilde(path).into_owned()))
+        })
+    }
+
     /// After loading the config file, filter directives consist of 3 parts:
     ///
     ///     1. directives from the configuration file
@@ -1518,6 +2562,15 @@ impl Config {
         self.opt.show_skipped
     }
 
+    pub fn explain_skips(&self) -> bool {
+        self.opt.explain_skips
+    }
+
+    /// Where to write the failure report, if a step fails
+    pub fn dump_report_on_failure(&self) -> Option<&PathBuf> {
+        self.opt.dump_report_on_failure.as_ref()
+    }
+
     pub fn open_remotes_in_new_terminal(&self) -> bool {
         self.config_file
             .windows
@@ -1579,6 +2632,96 @@ impl Config {
             .unwrap_or(false)
     }
 
+    /// Which Flatpak installations to update
+    #[cfg(target_os = "linux")]
+    pub fn flatpak_scope(&self) -> FlatpakScope {
+        self.config_file
+            .flatpak
+            .as_ref()
+            .and_then(|flatpak| flatpak.scope)
+            .unwrap_or_default()
+    }
+
+    /// Always pass `-y` to flatpak, regardless of `--yes`
+    #[cfg(target_os = "linux")]
+    pub fn flatpak_assume_yes(&self) -> bool {
+        self.config_file
+            .flatpak
+            .as_ref()
+            .and_then(|flatpak| flatpak.assume_yes)
+            .unwrap_or(false)
+    }
+
+    /// Ask before removing the unused runtimes, even with `--yes`
+    #[cfg(target_os = "linux")]
+    pub fn flatpak_confirm_removals(&self) -> bool {
+        self.config_file
+            .flatpak
+            .as_ref()
+            .and_then(|flatpak| flatpak.confirm_removals)
+            .unwrap_or(true)
+    }
+
+    /// Remove the disabled snap revisions during cleanup
+    #[cfg(target_os = "linux")]
+    pub fn snap_remove_disabled(&self) -> bool {
+        self.config_file
+            .snap
+            .as_ref()
+            .and_then(|snap| snap.remove_disabled)
+            .unwrap_or(false)
+    }
+
+    /// Ask before removing the disabled snap revisions, even with `--yes`
+    #[cfg(target_os = "linux")]
+    pub fn snap_confirm_removals(&self) -> bool {
+        self.config_file
+            .snap
+            .as_ref()
+            .and_then(|snap| snap.confirm_removals)
+            .unwrap_or(true)
+    }
+
+    /// How many times to retry the refs that failed to update
+    #[cfg(target_os = "linux")]
+    pub fn flatpak_retry(&self) -> u32 {
+        self.config_file
+            .flatpak
+            .as_ref()
+            .and_then(|flatpak| flatpak.retry)
+            .unwrap_or(0)
+    }
+
+    /// Run `flatpak repair` before updating
+    #[cfg(target_os = "linux")]
+    pub fn flatpak_repair(&self) -> bool {
+        self.config_file
+            .flatpak
+            .as_ref()
+            .and_then(|flatpak| flatpak.repair)
+            .unwrap_or(false)
+    }
+
+    /// Apps to install if they are missing, as `<app id>` or `<remote>:<app id>`
+    #[cfg(target_os = "linux")]
+    pub fn flatpak_apps(&self) -> &[String] {
+        self.config_file
+            .flatpak
+            .as_ref()
+            .and_then(|flatpak| flatpak.apps.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Runtimes that `flatpak uninstall --unused` should never remove
+    #[cfg(target_os = "linux")]
+    pub fn flatpak_keep_runtimes(&self) -> &[String] {
+        self.config_file
+            .flatpak
+            .as_ref()
+            .and_then(|flatpak| flatpak.keep_runtimes.as_deref())
+            .unwrap_or_default()
+    }
+
     #[cfg(target_os = "linux")]
     str_value!(linux, emerge_sync_flags);
 
@@ -1637,6 +2780,36 @@ impl Config {
             .unwrap_or(false)
     }
 
+    /// Python interpreters whose pip should be updated, instead of the detected one
+    pub fn python_interpreters(&self) -> Vec<PathBuf> {
+        self.config_file
+            .python
+            .as_ref()
+            .and_then(|python| python.interpreters.as_ref())
+            .map(|interpreters| {
+                interpreters
+                    .iter()
+                    .map(|interpreter| PathBuf::from(shellexpand::tilde(interpreter).into_owned()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Virtualenvs whose pip should be updated
+    pub fn python_virtualenvs(&self) -> Vec<PathBuf> {
+        self.config_file
+            .python
+            .as_ref()
+            .and_then(|python| python.virtualenvs.as_ref())
+            .map(|virtualenvs| {
+                virtualenvs
+                    .iter()
+                    .map(|venv| PathBuf::from(shellexpand::tilde(venv).into_owned()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     pub fn display_time(&self) -> bool {
         self.config_file
             .misc
@@ -1668,6 +2841,225 @@ impl Config {
             .and_then(|julia| julia.startup_file)
             .unwrap_or(true)
     }
+
+    /// Cargo projects whose `Cargo.lock` should be updated
+    pub fn cargo_projects(&self) -> Vec<PathBuf> {
+        self.config_file
+            .cargo
+            .as_ref()
+            .and_then(|cargo| cargo.projects.as_ref())
+            .map(|projects| {
+                projects
+                    .iter()
+                    .map(|project| PathBuf::from(shellexpand::tilde(project).into_owned()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The SDKMAN! candidates to upgrade, all installed candidates if empty
+    pub fn sdkman_candidates(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .sdkman
+            .as_ref()
+            .and_then(|sdkman| sdkman.candidates.as_ref())
+    }
+
+    /// Update all the environments listed by `conda env list`
+    pub fn conda_all_envs(&self) -> bool {
+        self.config_file
+            .conda
+            .as_ref()
+            .and_then(|conda| conda.all_envs)
+            .unwrap_or(false)
+    }
+
+    /// Conda environments to update in addition to `base`, as names or paths
+    pub fn conda_envs(&self) -> Option<&Vec<String>> {
+        self.config_file.conda.as_ref().and_then(|conda| conda.envs.as_ref())
+    }
+
+    /// Run `mas upgrade` even when `mas outdated` lists nothing
+    #[cfg(target_os = "macos")]
+    pub fn mas_force(&self) -> bool {
+        self.config_file
+            .macos
+            .as_ref()
+            .and_then(|macos| macos.mas_force)
+            .unwrap_or(false)
+    }
+
+    /// Install the Command Line Tools updates without asking
+    #[cfg(target_os = "macos")]
+    pub fn accept_all_macos_updates(&self) -> bool {
+        self.config_file
+            .macos
+            .as_ref()
+            .and_then(|macos| macos.accept_all_updates)
+            .unwrap_or(false)
+    }
+
+    /// The only Sparkle apps to update, all of them when unset
+    #[cfg(target_os = "macos")]
+    pub fn sparkle_apps(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .macos
+            .as_ref()
+            .and_then(|macos| macos.sparkle_apps.as_ref())
+    }
+
+    /// Install the latest version of the tools in `asdf.tools` and make it the default
+    #[cfg(unix)]
+    pub fn asdf_install_latest(&self) -> bool {
+        self.config_file
+            .asdf
+            .as_ref()
+            .and_then(|asdf| asdf.install_latest)
+            .unwrap_or(false)
+    }
+
+    /// The asdf tools to keep on their latest version
+    #[cfg(unix)]
+    pub fn asdf_tools(&self) -> &[String] {
+        self.config_file
+            .asdf
+            .as_ref()
+            .and_then(|asdf| asdf.tools.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Extra arguments for `port upgrade outdated`
+    #[cfg(target_os = "macos")]
+    pub fn macports_args(&self) -> Option<&str> {
+        self.config_file
+            .macos
+            .as_ref()
+            .and_then(|macos| macos.macports_args.as_deref())
+    }
+
+    /// Always run `tlmgr` in user mode, updating only the user TeX tree
+    pub fn tlmgr_user_mode(&self) -> bool {
+        self.config_file
+            .tex
+            .as_ref()
+            .and_then(|tex| tex.user_mode)
+            .unwrap_or(false)
+    }
+
+    /// Update a Go toolchain installed with `golang.org/dl` to the latest release
+    pub fn go_update_toolchain(&self) -> bool {
+        self.config_file
+            .go
+            .as_ref()
+            .and_then(|go| go.update_toolchain)
+            .unwrap_or(false)
+    }
+
+    /// Clean the Go build and module caches when cleaning up
+    pub fn go_clean_cache(&self) -> bool {
+        self.config_file
+            .go
+            .as_ref()
+            .and_then(|go| go.clean_cache)
+            .unwrap_or(false)
+    }
+
+    /// The rustup toolchains to update, all installed toolchains if empty
+    pub fn rustup_toolchains(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .rustup
+            .as_ref()
+            .and_then(|rustup| rustup.toolchains.as_ref())
+    }
+
+    /// The scope passed to `Update-Module`
+    pub fn powershell_scope(&self) -> Option<PowershellModuleScope> {
+        self.config_file
+            .powershell
+            .as_ref()
+            .and_then(|powershell| powershell.scope)
+    }
+
+    /// PowerShell modules that should not be updated
+    pub fn powershell_skip_modules(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .powershell
+            .as_ref()
+            .and_then(|powershell| powershell.skip.as_ref())
+    }
+
+    /// The gcloud components to update, all installed components if empty
+    pub fn gcloud_components(&self) -> Option<&Vec<String>> {
+        self.config_file
+            .gcloud
+            .as_ref()
+            .and_then(|gcloud| gcloud.components.as_ref())
+    }
+
+    /// Whether to install and update the gcloud alpha and beta components
+    pub fn gcloud_include_preview(&self) -> bool {
+        self.config_file
+            .gcloud
+            .as_ref()
+            .and_then(|gcloud| gcloud.include_preview)
+            .unwrap_or(false)
+    }
+
+    /// Extra arguments for `pkg upgrade`
+    #[cfg(target_os = "freebsd")]
+    pub fn freebsd_pkg_args(&self) -> Option<&str> {
+        self.config_file
+            .freebsd
+            .as_ref()
+            .and_then(|freebsd| freebsd.pkg_args.as_deref())
+    }
+
+    /// Whether to run `nix flake update` before upgrading
+    #[cfg(unix)]
+    pub fn nix_update_flake_inputs(&self) -> bool {
+        self.config_file
+            .nix
+            .as_ref()
+            .and_then(|nix| nix.update_flake_inputs)
+            .unwrap_or(false)
+    }
+
+    /// The directory of the flake whose inputs should be updated
+    #[cfg(unix)]
+    pub fn nix_flake_dir(&self) -> PathBuf {
+        self.config_file
+            .nix
+            .as_ref()
+            .and_then(|nix| nix.flake_dir.as_deref())
+            .map(|dir| PathBuf::from(shellexpand::tilde(dir).into_owned()))
+            .unwrap_or_else(|| crate::HOME_DIR.join(".config/home-manager"))
+    }
+
+    /// Keep the Nix generations younger than this many days when collecting garbage
+    #[cfg(unix)]
+    pub fn nix_gc_keep_days(&self) -> Option<u32> {
+        self.config_file.nix.as_ref().and_then(|nix| nix.gc_keep_days)
+    }
+
+    /// Deduplicate the Nix store when cleaning up
+    #[cfg(unix)]
+    pub fn nix_optimise(&self) -> bool {
+        self.config_file
+            .nix
+            .as_ref()
+            .and_then(|nix| nix.optimise)
+            .unwrap_or(false)
+    }
+
+    /// Report the packages whose version changed in the Nix profile
+    #[cfg(unix)]
+    pub fn nix_show_diff(&self) -> bool {
+        self.config_file
+            .nix
+            .as_ref()
+            .and_then(|nix| nix.show_diff)
+            .unwrap_or(false)
+    }
 }
 
 #[cfg(test)]
@@ -1684,6 +3076,37 @@ mod test {
         assert!(toml::from_str::<ConfigFile>(str).is_ok());
     }
 
+    #[test]
+    fn test_parse_env_file() {
+        let contents = "# comment\n\nFOO=bar\nexport BAZ = \"qux quux\"\nSINGLE='a=b'\ninvalid\n";
+        assert_eq!(
+            parse_env_file(contents),
+            vec![
+                ("FOO".to_string(), "bar".to_string()),
+                ("BAZ".to_string(), "qux quux".to_string()),
+                ("SINGLE".to_string(), "a=b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("18446744073709551615h").is_err());
+    }
+
+    #[test]
+    fn test_step_list_names() {
+        let list = Step::list();
+        assert_eq!(list.len(), Step::VARIANTS.len());
+        assert!(list.iter().all(|step| step.name.parse::<Step>().is_ok()));
+    }
+
     fn config() -> Config {
         Config {
             opt: CommandLineArgs::parse_from::<_, String>([]),
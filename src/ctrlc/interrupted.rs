@@ -1,19 +1,48 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-
-/// A global variable telling whether the application has been interrupted.
-static INTERRUPTED: AtomicBool = AtomicBool::new(false);
-
-/// Tells whether the program has been interrupted
-pub fn interrupted() -> bool {
-    INTERRUPTED.load(Ordering::SeqCst)
-}
-
-/// Clears the interrupted flag
-pub fn unset_interrupted() {
-    debug_assert!(INTERRUPTED.load(Ordering::SeqCst));
-    INTERRUPTED.store(false, Ordering::SeqCst)
-}
-
-pub fn set_interrupted() {
-    INTERRUPTED.store(true, Ordering::SeqCst)
-}
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::config::InterruptMode;
+
+/// A global variable telling whether the application has been interrupted.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether an interruption stops the running command and asks to retry the step, rather
+/// than letting it finish and skipping the remaining ones, see `misc.interrupt_mode`.
+static IMMEDIATE: AtomicBool = AtomicBool::new(false);
+
+/// The PID of the command that is running, or 0.
+static RUNNING_CHILD: AtomicU32 = AtomicU32::new(0);
+
+/// Tells whether the program has been interrupted
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Clears the interrupted flag
+pub fn unset_interrupted() {
+    debug_assert!(INTERRUPTED.load(Ordering::SeqCst));
+    INTERRUPTED.store(false, Ordering::SeqCst)
+}
+
+pub fn set_interrupted() {
+    INTERRUPTED.store(true, Ordering::SeqCst)
+}
+
+pub fn set_interrupt_mode(mode: InterruptMode) {
+    IMMEDIATE.store(mode == InterruptMode::Immediate, Ordering::SeqCst)
+}
+
+/// Tells whether an interruption should ask to retry the step
+pub fn immediate() -> bool {
+    IMMEDIATE.load(Ordering::SeqCst)
+}
+
+/// Record the command that is running, so that it can be killed on ctrl-c or when its
+/// step times out.
+pub fn set_running_child(pid: Option<u32>) {
+    RUNNING_CHILD.store(pid.unwrap_or(0), Ordering::SeqCst)
+}
+
+/// The PID of the command that is running
+pub fn running_child() -> Option<u32> {
+    Some(RUNNING_CHILD.load(Ordering::SeqCst)).filter(|&pid| pid != 0)
+}
@@ -3,11 +3,11 @@ mod interrupted;
 #[cfg(unix)]
 mod unix;
 #[cfg(unix)]
-pub use self::unix::set_handler;
+pub use self::unix::{kill_running_child, prepare_child, set_handler, wait_child};
 
 #[cfg(windows)]
 mod windows;
 #[cfg(windows)]
-pub use self::windows::set_handler;
+pub use self::windows::{kill_running_child, prepare_child, set_handler, wait_child};
 
 pub use self::interrupted::*;
@@ -1,10 +1,93 @@
 //! SIGINT handling in Unix systems.
-use crate::ctrlc::interrupted::set_interrupted;
-use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::fs::File;
+use std::io;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, ExitStatus};
 
-/// Handle SIGINT. Set the interruption flag.
+use crate::ctrlc::interrupted::{immediate, interrupted, running_child, set_interrupted};
+use nix::errno::Errno;
+use nix::sys::signal::{kill, pthread_sigmask, sigaction, SaFlags, SigAction, SigHandler, SigSet, SigmaskHow, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{getpgrp, tcsetpgrp, Pid};
+
+/// Handle SIGINT. Set the interruption flag and, in immediate mode, stop the running
+/// command. Exit if the flag was already set.
+///
+/// In graceful mode, the running command is in its own process group, so the SIGINT of
+/// the terminal doesn't reach it.
 extern "C" fn handle_sigint(_: i32) {
-    set_interrupted()
+    // Only async-signal-safe functions can be called here
+    if interrupted() {
+        kill_running_child();
+        unsafe { nix::libc::_exit(130) }
+    }
+
+    set_interrupted();
+    if immediate() {
+        kill_running_child();
+    }
+}
+
+/// Stop the command that is running, if any.
+pub fn kill_running_child() {
+    if let Some(pid) = running_child().and_then(|pid| i32::try_from(pid).ok()) {
+        // SIGTERM rather than SIGKILL, so that sudo forwards it to the command it runs
+        kill(Pid::from_raw(pid), Signal::SIGTERM).ok();
+    }
+}
+
+/// Prepare a command whose PID will be recorded with `set_running_child`: in graceful
+/// mode, it runs in its own process group so that it can finish when ctrl-c is pressed.
+pub fn prepare_child(command: &mut Command) {
+    if !immediate() {
+        command.process_group(0);
+    }
+}
+
+/// Wait for a command prepared with `prepare_child`.
+///
+/// A command in its own process group is stopped when it reads from the terminal or
+/// changes its settings, like a background job. It is then given the terminal until it
+/// exits, so ctrl-c reaches it again while it waits for input.
+pub fn wait_child(child: &mut Child) -> io::Result<ExitStatus> {
+    if immediate() {
+        return child.wait();
+    }
+
+    let pid = Pid::from_raw(i32::try_from(child.id()).map_err(io::Error::other)?);
+    let mut terminal = None;
+    let status = loop {
+        match waitpid(pid, Some(WaitPidFlag::WUNTRACED)) {
+            Ok(WaitStatus::Exited(_, code)) => break Ok(ExitStatus::from_raw(code << 8)),
+            Ok(WaitStatus::Signaled(_, signal, core_dumped)) => {
+                break Ok(ExitStatus::from_raw(signal as i32 | if core_dumped { 0x80 } else { 0 }))
+            }
+            Ok(WaitStatus::Stopped(_, Signal::SIGTTIN | Signal::SIGTTOU)) => {
+                if terminal.is_none() {
+                    terminal = File::open("/dev/tty").ok();
+                    if let Some(tty) = &terminal {
+                        tcsetpgrp(tty, pid).ok();
+                    }
+                }
+                kill(Pid::from_raw(-pid.as_raw()), Signal::SIGCONT).ok();
+            }
+            // The SIGINT handler interrupts the wait
+            Ok(_) | Err(Errno::EINTR) => (),
+            Err(e) => break Err(e.into()),
+        }
+    };
+
+    if let Some(tty) = terminal {
+        // Topgrade is in the background now, and would be stopped by SIGTTOU
+        let mut sigttou = SigSet::empty();
+        sigttou.add(Signal::SIGTTOU);
+        let mut mask = SigSet::empty();
+        pthread_sigmask(SigmaskHow::SIG_BLOCK, Some(&sigttou), Some(&mut mask)).ok();
+        tcsetpgrp(&tty, getpgrp()).ok();
+        pthread_sigmask(SigmaskHow::SIG_SETMASK, Some(&mask), None).ok();
+    }
+
+    status
 }
 
 /// Set the necessary signal handlers.
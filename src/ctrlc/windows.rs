@@ -1,5 +1,10 @@
 //! A stub for Ctrl + C handling.
-use crate::ctrlc::interrupted::set_interrupted;
+use std::io;
+use std::os::windows::process::CommandExt as _;
+use std::process::{Child, Command, ExitStatus};
+
+use crate::command::CommandExt;
+use crate::ctrlc::interrupted::{immediate, interrupted, running_child, set_interrupted};
 use tracing::error;
 use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
 use winapi::um::consoleapi::SetConsoleCtrlHandler;
@@ -8,13 +13,48 @@ use winapi::um::wincon::CTRL_C_EVENT;
 extern "system" fn handler(ctrl_type: DWORD) -> BOOL {
     match ctrl_type {
         CTRL_C_EVENT => {
+            if interrupted() {
+                kill_running_child();
+                std::process::exit(130);
+            }
+
+            // In graceful mode, the running command is in its own process group, which
+            // doesn't get the ctrl-c
             set_interrupted();
+            if immediate() {
+                kill_running_child();
+            }
             TRUE
         }
         _ => FALSE,
     }
 }
 
+/// Stop the command that is running, if any.
+pub fn kill_running_child() {
+    if let Some(pid) = running_child() {
+        Command::new("taskkill")
+            .args(["/F", "/T", "/PID", &pid.to_string()])
+            .output_checked()
+            .ok();
+    }
+}
+
+/// Prepare a command whose PID will be recorded with `set_running_child`: in graceful
+/// mode, it runs in a new process group, which ignores ctrl-c, so that it can finish.
+pub fn prepare_child(command: &mut Command) {
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+
+    if !immediate() {
+        command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+}
+
+/// Wait for a command prepared with `prepare_child`.
+pub fn wait_child(child: &mut Child) -> io::Result<ExitStatus> {
+    child.wait()
+}
+
 pub fn set_handler() {
     if 0 == unsafe { SetConsoleCtrlHandler(Some(handler), TRUE) } {
         error!("Cannot set a control C handler")
@@ -86,6 +86,55 @@ impl Display for SkipStep {
     }
 }
 
+/// Environment variable set by the controller when running Topgrade on a remote host,
+/// asking it to exit with [`REBOOT_REQUIRED_EXIT_CODE`] when the host needs a reboot.
+pub const REPORT_REBOOT_ENV: &str = "TOPGRADE_REPORT_REBOOT";
+
+/// Exit code of a successful remote run on a host that needs a reboot.
+pub const REBOOT_REQUIRED_EXIT_CODE: i32 = 10;
+
+#[derive(Error, Debug)]
+pub struct NoChanges;
+
+impl Display for NoChanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", t!("No changes"))
+    }
+}
+
+#[derive(Error, Debug)]
+pub struct RebootRequired;
+
+impl Display for RebootRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", t!("Reboot required"))
+    }
+}
+
+/// The step succeeded, with a description of what it changed.
+#[derive(Error, Debug)]
+pub struct Updated(pub Vec<String>);
+
+impl Display for Updated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.join(", "))
+    }
+}
+
+/// The step succeeded, but some of the packages it upgrades failed.
+#[derive(Error, Debug)]
+pub struct PartialFailure(pub Vec<String>);
+
+impl Display for PartialFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            t!("Failed to upgrade: {packages}", packages = self.0.join(", "))
+        )
+    }
+}
+
 #[cfg(all(windows, feature = "self-update"))]
 #[derive(Error, Debug)]
 pub struct Upgraded(pub ExitStatus);
@@ -1,14 +1,44 @@
 //! Utilities for command execution
 use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, Read, Write};
 use std::path::Path;
-use std::process::{Child, Command, ExitStatus, Output};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 use color_eyre::eyre::Result;
 use rust_i18n::t;
 use tracing::debug;
 
 use crate::command::CommandExt;
-use crate::error::DryRun;
+use crate::ctrlc;
+use crate::error::{DryRun, TopgradeError};
+use crate::terminal;
+
+/// The maximum number of bytes captured from each of stdout and stderr by `Executor::output`.
+static MAX_CAPTURED_OUTPUT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Limit the output captured by `Executor::output`, see `misc.max_captured_output`.
+pub fn set_max_captured_output(max: Option<usize>) {
+    MAX_CAPTURED_OUTPUT.store(max.unwrap_or(usize::MAX), Ordering::Relaxed);
+}
+
+/// The log file of the running step, see `misc.log_dir`.
+static STEP_LOG: Mutex<Option<File>> = Mutex::new(None);
+
+/// Copy the output of the commands run by `Executor` to `log`, until it is unset.
+pub fn set_step_log(log: Option<File>) {
+    *STEP_LOG.lock().unwrap() = log;
+}
+
+fn step_log() -> Option<File> {
+    STEP_LOG.lock().unwrap().as_ref().and_then(|log| {
+        log.try_clone()
+            .map_err(|e| debug!("Could not use the step log: {e}"))
+            .ok()
+    })
+}
 
 /// An enum telling whether Topgrade should perform dry runs or actually perform the steps.
 #[derive(Clone, Copy, Debug)]
@@ -167,13 +197,20 @@ impl Executor {
     }
 
     /// See `std::process::Command::output`
+    ///
+    /// Stdout and stderr are truncated to `misc.max_captured_output` bytes each.
     pub fn output(&mut self) -> Result<ExecutorOutput> {
         match self {
             Executor::Wet(c) => {
-                // We should use `output()` here rather than `output_checked()` since
-                // their semantics and behaviors are different.
-                #[allow(clippy::disallowed_methods)]
-                Ok(ExecutorOutput::Wet(c.output()?))
+                let output = output_capped(c, MAX_CAPTURED_OUTPUT.load(Ordering::Relaxed))?;
+
+                if let Some(mut log) = step_log() {
+                    log.write_all(&output.stdout)
+                        .and_then(|()| log.write_all(&output.stderr))
+                        .unwrap_or_else(|e| debug!("Could not write the step log: {e}"));
+                }
+
+                Ok(ExecutorOutput::Wet(output))
             }
             Executor::Dry(c) => {
                 c.dry_run();
@@ -200,6 +237,154 @@ impl Executor {
             }
         }
     }
+
+    /// Like `status_checked`, but stderr is kept while it is printed, and a failure
+    /// is reported as `TopgradeError::ProcessFailedWithOutput` so that callers can
+    /// inspect the error message of the command.
+    #[cfg(unix)]
+    pub fn status_checked_with_stderr(&mut self) -> Result<()> {
+        match self {
+            Executor::Wet(c) => {
+                let (status, stderr) = status_teed(c, step_log(), Kept::Stderr)?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    let program = c.get_program().to_string_lossy().into_owned();
+                    let stderr = String::from_utf8_lossy(&stderr).into_owned();
+                    Err(TopgradeError::ProcessFailedWithOutput(program, status, stderr).into())
+                }
+            }
+            Executor::Dry(c) => {
+                c.dry_run();
+                Ok(())
+            }
+        }
+    }
+
+    /// Like `status_checked`, but stdout is kept while it is printed, and returned so
+    /// that callers can parse it. Dry runs return an empty string.
+    #[allow(dead_code)]
+    pub fn status_checked_with_stdout(&mut self) -> Result<String> {
+        match self {
+            Executor::Wet(c) => {
+                let (status, stdout) = status_teed(c, step_log(), Kept::Stdout)?;
+                if status.success() {
+                    Ok(String::from_utf8_lossy(&stdout).into_owned())
+                } else {
+                    let program = c.get_program().to_string_lossy().into_owned();
+                    Err(TopgradeError::ProcessFailed(program, status).into())
+                }
+            }
+            Executor::Dry(c) => {
+                c.dry_run();
+                Ok(String::new())
+            }
+        }
+    }
+}
+
+/// The output that `status_teed` returns, besides printing it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kept {
+    Nothing,
+    Stdout,
+    Stderr,
+}
+
+/// Like `Command::status`, but the output goes through `terminal::write_output`,
+/// so that it can be buffered, and is also copied to `log`. The `keep` stream is
+/// returned as well.
+///
+/// Stdout stays attached to the terminal when it doesn't need to be logged, buffered or kept.
+fn status_teed(command: &mut Command, log: Option<File>, keep: Kept) -> Result<(ExitStatus, Vec<u8>)> {
+    if log.is_some() || terminal::is_buffering() || keep == Kept::Stdout {
+        command.stdout(Stdio::piped());
+    }
+    command.stderr(Stdio::piped());
+    let stderr_log = log.as_ref().map(File::try_clone).transpose()?;
+    ctrlc::prepare_child(command);
+    let mut child = command.spawn_checked()?;
+    ctrlc::set_running_child(Some(child.id()));
+
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let stderr = std::thread::spawn(move || tee(stderr, true, stderr_log, keep == Kept::Stderr));
+    let stdout = child
+        .stdout
+        .take()
+        .map(|stdout| tee(stdout, false, log, keep == Kept::Stdout));
+    let stderr = stderr.join().expect("stderr reader panicked");
+
+    let status = ctrlc::wait_child(&mut child);
+    ctrlc::set_running_child(None);
+    let stdout = stdout.transpose()?.unwrap_or_default();
+    let stderr = stderr?;
+    Ok((status?, if keep == Kept::Stdout { stdout } else { stderr }))
+}
+
+/// Copy `reader` to both the terminal and `log` as it is read, returning what was
+/// read when `keep` is set.
+fn tee(mut reader: impl Read, stderr: bool, mut log: Option<File>, keep: bool) -> io::Result<Vec<u8>> {
+    let mut kept = Vec::new();
+    let mut buffer = [0; 8192];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(kept);
+        }
+        terminal::write_output(&buffer[..read], stderr)?;
+        if let Some(log) = &mut log {
+            log.write_all(&buffer[..read])?;
+        }
+        if keep {
+            kept.extend_from_slice(&buffer[..read]);
+        }
+    }
+}
+
+/// Like `Command::output`, but keeps at most `max` bytes of stdout and stderr.
+fn output_capped(command: &mut Command, max: usize) -> Result<Output> {
+    // Same as `Command::output`, which doesn't inherit stdin
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    ctrlc::prepare_child(command);
+    // We should use `spawn()` here rather than `spawn_checked()` since
+    // their semantics and behaviors are different.
+    #[allow(clippy::disallowed_methods)]
+    let mut child = command.spawn()?;
+    // Keep track of the command, so that the step timeout can stop it
+    ctrlc::set_running_child(Some(child.id()));
+
+    let output = (|| {
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        // Both pipes have to be read at the same time, or the child could block on a full one
+        let stderr = std::thread::spawn(move || read_capped(stderr, max));
+        let stdout = read_capped(stdout, max)?;
+        let stderr = stderr.join().expect("stderr reader panicked")?;
+
+        Ok(Output {
+            status: ctrlc::wait_child(&mut child)?,
+            stdout,
+            stderr,
+        })
+    })();
+    ctrlc::set_running_child(None);
+    output
+}
+
+/// Read `reader` to the end, keeping at most `max` bytes.
+fn read_capped(mut reader: impl Read, max: usize) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    reader.by_ref().take(max as u64).read_to_end(&mut output)?;
+
+    // Keep draining the pipe so that the child can finish writing
+    if io::copy(&mut reader, &mut io::sink())? > 0 {
+        output.extend_from_slice(b"\n[output truncated]\n");
+    }
+
+    Ok(output)
 }
 
 pub enum ExecutorOutput {
@@ -262,7 +447,20 @@ impl CommandExt for Executor {
 
     fn status_checked_with(&mut self, succeeded: impl Fn(ExitStatus) -> Result<(), ()>) -> Result<()> {
         match self {
-            Executor::Wet(c) => c.status_checked_with(succeeded),
+            Executor::Wet(c) => {
+                let log = step_log();
+                if log.is_none() && !terminal::is_buffering() {
+                    return c.status_checked_with(succeeded);
+                }
+
+                let (status, _) = status_teed(c, log, Kept::Nothing)?;
+                if succeeded(status).is_ok() {
+                    Ok(())
+                } else {
+                    let program = c.get_program().to_string_lossy().into_owned();
+                    Err(TopgradeError::ProcessFailed(program, status).into())
+                }
+            }
             Executor::Dry(c) => {
                 c.dry_run();
                 Ok(())
@@ -0,0 +1,182 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+#[cfg(windows)]
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context, Result};
+use etcetera::base_strategy::BaseStrategy;
+use rust_i18n::t;
+use tracing::{debug, error};
+
+#[cfg(windows)]
+use crate::command::CommandExt;
+#[cfg(unix)]
+use crate::XDG_DIRS;
+
+#[cfg(windows)]
+use crate::WINDOWS_DIRS;
+
+/// The PID of the Topgrade holding the lock, for the Topgrade it starts again
+/// after upgrading itself.
+const LOCK_HOLDER_ENV: &str = "TOPGRADE_LOCK_HOLDER";
+
+/// The lock of this run, kept until `release` so that it is also removed on `exit`.
+static LOCK: Mutex<Option<Lockfile>> = Mutex::new(None);
+
+/// A lock preventing two Topgrade runs from upgrading the system at the same time.
+///
+/// The lock file contains the PID of the process holding it, so a lock left behind
+/// by a crashed run can be detected and taken over. It is removed when dropped.
+struct Lockfile {
+    path: PathBuf,
+}
+
+/// Acquire the lock, failing if another running Topgrade holds it and `force` is not set.
+pub fn acquire(force: bool) -> Result<()> {
+    let lockfile = Lockfile::acquire(force)?;
+    *LOCK.lock().unwrap() = Some(lockfile);
+    Ok(())
+}
+
+/// Release the lock, if it was acquired.
+pub fn release() {
+    LOCK.lock().unwrap().take();
+}
+
+impl Lockfile {
+    fn acquire(force: bool) -> Result<Self> {
+        let path = lock_file_path();
+        let pid = std::process::id();
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // A stale lock is removed, then creating the file is tried once more
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(pid.to_string().as_bytes())
+                        .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+                    env::set_var(LOCK_HOLDER_ENV, pid.to_string());
+                    debug!("Acquired lock {}", path.display());
+                    return Ok(Lockfile { path });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+                Err(e) => return Err(e).wrap_err_with(|| format!("Failed to create {}", path.display())),
+            }
+
+            match lock_holder(&path) {
+                // Held by this process, or by the Topgrade that started it again after upgrading itself
+                Some(holder)
+                    if holder == pid || env::var(LOCK_HOLDER_ENV).is_ok_and(|env| env == holder.to_string()) =>
+                {
+                    debug!("Lock {} is already held by this run", path.display());
+                    return Ok(Lockfile { path });
+                }
+                Some(holder) if is_running(holder) => {
+                    if !force {
+                        return Err(eyre!(
+                            "{}",
+                            t!(
+                                "Topgrade is already running (PID {pid}). Use --force to run anyway, or remove {path} if this is wrong",
+                                pid = holder,
+                                path = path.display()
+                            )
+                        ));
+                    }
+
+                    debug!("Ignoring lock {} held by PID {holder}", path.display());
+                    fs::write(&path, pid.to_string())
+                        .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+                    return Ok(Lockfile { path });
+                }
+                _ => {
+                    debug!("Removing stale lock {}", path.display());
+                    match fs::remove_file(&path) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                            return Err(e).wrap_err_with(|| format!("Failed to remove {}", path.display()))
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        Err(eyre!(
+            "{}",
+            t!(
+                "Topgrade is already running (PID {pid}). Use --force to run anyway, or remove {path} if this is wrong",
+                pid = lock_holder(&path).map(|holder| holder.to_string()).unwrap_or_default(),
+                path = path.display()
+            )
+        ))
+    }
+}
+
+impl Drop for Lockfile {
+    fn drop(&mut self) {
+        match fs::read_to_string(&self.path) {
+            // Another run took the lock over with `--force`, or it belongs to the
+            // Topgrade that started this one, leave it alone.
+            Ok(contents) if contents.trim() != std::process::id().to_string() => (),
+            _ => {
+                if let Err(e) = fs::remove_file(&self.path) {
+                    error!("Could not remove the lock file {}: {}", self.path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+/// The PID in the lock file, `None` if it is stale or unreadable.
+fn lock_holder(path: &Path) -> Option<u32> {
+    let read = || {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| contents.trim().parse::<u32>().ok())
+    };
+    // The file can still be empty right after another run created it
+    read().or_else(|| {
+        thread::sleep(Duration::from_millis(100));
+        read()
+    })
+}
+
+/// Return Topgrade's lock file path.
+fn lock_file_path() -> PathBuf {
+    #[cfg(unix)]
+    let dir = XDG_DIRS.state_dir().unwrap_or_else(|| XDG_DIRS.data_dir());
+
+    #[cfg(windows)]
+    let dir = WINDOWS_DIRS.data_dir();
+
+    dir.join("topgrade.lock")
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    use nix::errno::Errno;
+    use nix::sys::signal::kill;
+    use nix::unistd::Pid;
+
+    let Ok(pid) = i32::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks whether the process exists.
+    matches!(kill(Pid::from_raw(pid), None), Ok(()) | Err(Errno::EPERM))
+}
+
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+    Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {pid}"), "/NH"])
+        .output_checked_utf8()
+        .map(|output| output.stdout.split_whitespace().any(|word| word == pid.to_string()))
+        .unwrap_or(false)
+}
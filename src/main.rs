@@ -1,7 +1,8 @@
 #![allow(clippy::cognitive_complexity)]
 
 use std::env;
-use std::io;
+use std::fs;
+use std::io::{self, IsTerminal};
 use std::path::PathBuf;
 use std::process::exit;
 use std::time::Duration;
@@ -9,6 +10,7 @@ use std::time::Duration;
 use crate::breaking_changes::{first_run_of_major_release, print_breaking_changes, should_skip, write_keep_file};
 use clap::CommandFactory;
 use clap::{crate_version, Parser};
+use color_eyre::eyre::eyre;
 use color_eyre::eyre::Context;
 use color_eyre::eyre::Result;
 use console::Key;
@@ -22,9 +24,10 @@ use rust_i18n::{i18n, t};
 use tracing::debug;
 
 use self::config::{CommandLineArgs, Config, Step};
-use self::error::StepFailed;
 #[cfg(all(windows, feature = "self-update"))]
 use self::error::Upgraded;
+use self::error::{RebootRequired, StepFailed, REBOOT_REQUIRED_EXIT_CODE, REPORT_REBOOT_ENV};
+use self::report::StepResult;
 use self::steps::{remote::*, *};
 use self::terminal::*;
 
@@ -37,6 +40,8 @@ mod ctrlc;
 mod error;
 mod execution_context;
 mod executor;
+mod failure_report;
+mod lockfile;
 mod report;
 mod runner;
 #[cfg(windows)]
@@ -93,6 +98,16 @@ fn run() -> Result<()> {
         return Ok(());
     }
 
+    if opt.list_steps_json {
+        println!("{}", serde_json::to_string_pretty(&Step::list())?);
+        return Ok(());
+    }
+
+    if let Some(text) = &opt.emit_separator {
+        print_separator(text);
+        return Ok(());
+    }
+
     for env in opt.env_variables() {
         let mut splitted = env.split('=');
         let var = splitted.next().unwrap();
@@ -111,11 +126,54 @@ fn run() -> Result<()> {
     }
 
     let config = Config::load(opt)?;
+
+    // Variables set with `--env` take precedence over the configuration
+    let set_with_env_option = |var: &str| {
+        config
+            .env_variables()
+            .iter()
+            .any(|env| env.split('=').next() == Some(var))
+    };
+
+    if let Some(proxy) = config.proxy() {
+        for var in ["http_proxy", "https_proxy", "HTTP_PROXY", "HTTPS_PROXY"] {
+            if !set_with_env_option(var) {
+                env::set_var(var, proxy);
+            }
+        }
+    }
+
+    if config.brew_no_analytics() && !set_with_env_option("HOMEBREW_NO_ANALYTICS") {
+        env::set_var("HOMEBREW_NO_ANALYTICS", "1");
+    }
+    for (var, remote) in [
+        ("HOMEBREW_BREW_GIT_REMOTE", config.brew_git_remote()),
+        ("HOMEBREW_CORE_GIT_REMOTE", config.brew_core_git_remote()),
+    ] {
+        if let Some(remote) = remote {
+            if !set_with_env_option(var) {
+                env::set_var(var, remote);
+            }
+        }
+    }
+
+    if let Some(env_file) = config.env_file() {
+        let contents = fs::read_to_string(&env_file)
+            .wrap_err_with(|| format!("Failed to read the env file {}", env_file.display()))?;
+        for (var, value) in config::parse_env_file(&contents) {
+            if !set_with_env_option(&var) {
+                env::set_var(var, value);
+            }
+        }
+    }
+
     // Update the logger with the full filter directives.
     update_tracing(&reload_handle, &config.tracing_filter_directives())?;
     set_title(config.set_title());
     display_time(config.display_time());
     set_desktop_notifications(config.notify_each_step());
+    executor::set_max_captured_output(config.max_captured_output());
+    ctrlc::set_interrupt_mode(config.interrupt_mode());
 
     debug!("Version: {}", crate_version!());
     debug!("OS: {}", env!("TARGET"));
@@ -132,6 +190,23 @@ fn run() -> Result<()> {
         }
     }
 
+    // The output of the commands goes through a pipe, so their prompts may not show up
+    if (config.quiet() || config.log_dir().is_some())
+        && !config.dry_run()
+        && !config.yes_to_all()
+        && io::stdin().is_terminal()
+    {
+        return Err(eyre!(
+            "{}",
+            t!("--quiet and misc.log_dir hide the prompts of the commands, use them with --yes")
+        ));
+    }
+
+    // Taken once inside tmux. Topgrade started again by self-update keeps it.
+    if !config.dry_run() {
+        lockfile::acquire(config.force())?;
+    }
+
     let powershell = powershell::Powershell::new();
     let should_run_powershell = powershell.profile().is_some() && config.should_run(Step::Powershell);
     let emacs = emacs::Emacs::new();
@@ -155,6 +230,7 @@ fn run() -> Result<()> {
         if prompt_yesno("Confirmed?")? {
             write_keep_file()?;
         } else {
+            lockfile::release();
             exit(1);
         }
     }
@@ -191,8 +267,14 @@ fn run() -> Result<()> {
     }
 
     if let Some(topgrades) = config.remote_topgrades() {
+        // Tell forced remote dry runs apart from real updates in the summary
+        let suffix = if config.remote_dry_run_always() && !run_type.dry() {
+            " (dry run)"
+        } else {
+            ""
+        };
         for remote_topgrade in topgrades.iter().filter(|t| config.should_execute_remote(hostname(), t)) {
-            runner.execute(Step::Remotes, format!("Remote ({remote_topgrade})"), || {
+            runner.execute(Step::Remotes, format!("Remote ({remote_topgrade}){suffix}"), || {
                 ssh::ssh_step(&ctx, remote_topgrade)
             })?;
         }
@@ -204,7 +286,9 @@ fn run() -> Result<()> {
         runner.execute(Step::WslUpdate, "WSL", || windows::update_wsl(&ctx))?;
         runner.execute(Step::Chocolatey, "Chocolatey", || windows::run_chocolatey(&ctx))?;
         runner.execute(Step::Scoop, "Scoop", || windows::run_scoop(&ctx))?;
+        runner.execute(Step::Winget, "Winget export", || windows::run_winget_export(&ctx))?;
         runner.execute(Step::Winget, "Winget", || windows::run_winget(&ctx))?;
+        runner.execute(Step::Winget, "Winget configure", || windows::run_winget_configure(&ctx))?;
         runner.execute(Step::System, "Windows update", || windows::windows_update(&ctx))?;
         runner.execute(Step::MicrosoftStore, "Microsoft Store", || {
             windows::microsoft_store(&ctx)
@@ -237,7 +321,10 @@ fn run() -> Result<()> {
         runner.execute(Step::Protonup, "protonup", || linux::run_protonup_update(&ctx))?;
         runner.execute(Step::Distrobox, "distrobox", || linux::run_distrobox_update(&ctx))?;
         runner.execute(Step::DkpPacman, "dkp-pacman", || linux::run_dkp_pacman_update(&ctx))?;
-        runner.execute(Step::System, "pihole", || linux::run_pihole_update(&ctx))?;
+        // pihole is part of the system step, but only the distribution upgrade can download without installing
+        if !config.download_only() {
+            runner.execute(Step::System, "pihole", || linux::run_pihole_update(&ctx))?;
+        }
         runner.execute(Step::Firmware, "Firmware upgrades", || linux::run_fwupdmgr(&ctx))?;
         runner.execute(Step::Restarts, "Restarts", || linux::run_needrestart(&ctx))?;
 
@@ -272,6 +359,9 @@ fn run() -> Result<()> {
         })?;
         runner.execute(Step::Macports, "MacPorts", || macos::run_macports(&ctx))?;
         runner.execute(Step::Xcodes, "Xcodes", || macos::update_xcodes(&ctx))?;
+        runner.execute(Step::CommandLineTools, "Command Line Tools", || {
+            macos::update_command_line_tools(&ctx)
+        })?;
         runner.execute(Step::Sparkle, "Sparkle", || macos::run_sparkle(&ctx))?;
         runner.execute(Step::Mas, "App Store", || macos::run_mas(&ctx))?;
         runner.execute(Step::System, "System upgrade", || macos::upgrade_macos(&ctx))?;
@@ -362,9 +452,16 @@ fn run() -> Result<()> {
     runner.execute(Step::Dotnet, ".NET", || generic::run_dotnet_upgrade(&ctx))?;
     runner.execute(Step::Choosenim, "choosenim", || generic::run_choosenim(&ctx))?;
     runner.execute(Step::Cargo, "cargo", || generic::run_cargo_update(&ctx))?;
+    for project in config.cargo_projects() {
+        runner.execute(Step::Cargo, format!("cargo ({})", project.display()), || {
+            generic::run_cargo_project_update(&ctx, &project)
+        })?;
+    }
     runner.execute(Step::Flutter, "Flutter", || generic::run_flutter_upgrade(&ctx))?;
+    runner.execute(Step::Go, "Go toolchain", || go::run_go_toolchain_update(&ctx))?;
     runner.execute(Step::Go, "go-global-update", || go::run_go_global_update(&ctx))?;
     runner.execute(Step::Go, "gup", || go::run_go_gup(&ctx))?;
+    runner.execute(Step::Go, "Go cache", || go::run_go_clean_cache(&ctx))?;
     runner.execute(Step::Emacs, "Emacs", || emacs.upgrade(&ctx))?;
     runner.execute(Step::Opam, "opam", || generic::run_opam_update(&ctx))?;
     runner.execute(Step::Vcpkg, "vcpkg", || generic::run_vcpkg_update(&ctx))?;
@@ -384,6 +481,9 @@ fn run() -> Result<()> {
     runner.execute(Step::PipReviewLocal, "pip-review (local)", || {
         generic::run_pip_review_local_update(&ctx)
     })?;
+    runner.execute(Step::PipVirtualenvs, "pip (virtualenvs)", || {
+        generic::run_pip_virtualenvs_update(&ctx)
+    })?;
     runner.execute(Step::Pipupgrade, "pipupgrade", || generic::run_pipupgrade_update(&ctx))?;
     runner.execute(Step::Ghcup, "ghcup", || generic::run_ghcup_update(&ctx))?;
     runner.execute(Step::Stack, "stack", || generic::run_stack_update(&ctx))?;
@@ -404,6 +504,11 @@ fn run() -> Result<()> {
         node::run_volta_packages_upgrade(&ctx)
     })?;
     runner.execute(Step::Containers, "Containers", || containers::run_containers(&ctx))?;
+    for file in config.compose_files() {
+        runner.execute(Step::Containers, format!("Compose ({})", file.display()), || {
+            containers::run_compose_update(&ctx, &file)
+        })?;
+    }
     runner.execute(Step::Deno, "deno", || node::deno_upgrade(&ctx))?;
     runner.execute(Step::Composer, "composer", || generic::run_composer_update(&ctx))?;
     runner.execute(Step::Krew, "krew", || generic::run_krew_upgrade(&ctx))?;
@@ -455,6 +560,16 @@ fn run() -> Result<()> {
         }
     }
 
+    if let Some(hooks_dir) = config.hooks_dir() {
+        for (name, hook) in generic::hooks(&hooks_dir) {
+            if config.should_run_custom_command(&name) {
+                runner.execute(Step::CustomCommands, name.clone(), || {
+                    generic::run_hook(&name, &hook, &ctx)
+                })?;
+            }
+        }
+    }
+
     if config.should_run(Step::Vagrant) {
         if let Ok(boxes) = vagrant::collect_boxes(&ctx) {
             for vagrant_box in boxes {
@@ -469,17 +584,32 @@ fn run() -> Result<()> {
     if !runner.report().data().is_empty() {
         print_separator(t!("Summary"));
 
+        let summary_filter = config.summary_filter();
+        // With only `--explain-skips`, the skipped steps are listed in their own section
+        let show_skipped = config.verbose() || config.show_skipped();
         for (key, result) in runner.report().data() {
-            print_result(key, result);
+            if result.shown_with(summary_filter) && (show_skipped || !matches!(result, StepResult::Skipped(_))) {
+                print_result(key, result);
+            }
+        }
+
+        let skipped = runner.report().skipped_by_reason();
+        if config.explain_skips() && !skipped.is_empty() {
+            print_separator(t!("Skipped steps"));
+            for (reason, steps) in skipped {
+                println!("{reason}\n    {}", steps.join(", "));
+                if let Some(hint) = report::skip_hint(reason) {
+                    println!("    {hint}");
+                }
+            }
         }
 
         #[cfg(target_os = "linux")]
         {
-            if let Ok(distribution) = &distribution {
-                distribution.show_summary();
-            }
+            linux::show_summary(distribution.as_ref().ok().copied(), &config);
         }
     }
+    runner.write_summary_log();
 
     let mut post_command_failed = false;
     if let Some(commands) = config.post_commands() {
@@ -522,15 +652,36 @@ fn run() -> Result<()> {
         )
     }
 
+    #[cfg(target_os = "linux")]
+    let reboot_required = linux::reboot_required();
+    #[cfg(not(target_os = "linux"))]
+    let reboot_required = false;
+
     if failed {
+        if let Some(path) = config.dump_report_on_failure() {
+            match failure_report::write(path, runner.report(), &config) {
+                Ok(()) => print_info(t!("Wrote the failure report to {path}", path = path.display())),
+                Err(e) => print_warning(t!(
+                    "Could not write the failure report {path}: {error}",
+                    path = path.display(),
+                    error = e
+                )),
+            }
+        }
         Err(StepFailed.into())
+    } else if reboot_required && env::var_os(REPORT_REBOOT_ENV).is_some() && !config.dry_run() {
+        // Tell the controller running this remote Topgrade
+        Err(RebootRequired.into())
     } else {
         Ok(())
     }
 }
 
 fn main() {
-    match run() {
+    let result = run();
+    // `exit` doesn't run the destructors
+    lockfile::release();
+    match result {
         Ok(()) => {
             exit(0);
         }
@@ -542,6 +693,10 @@ fn main() {
                 }
             }
 
+            if error.downcast_ref::<RebootRequired>().is_some() {
+                exit(REBOOT_REQUIRED_EXIT_CODE);
+            }
+
             let skip_print = (error.downcast_ref::<StepFailed>().is_some())
                 || (error
                     .downcast_ref::<io::Error>()
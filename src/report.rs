@@ -1,7 +1,20 @@
 use std::borrow::Cow;
+use std::fmt::Display;
+
+use rust_i18n::t;
+
+use crate::config::SummaryFilter;
 
 pub enum StepResult {
     Success,
+    /// The step succeeded without changing anything.
+    NoChanges,
+    /// The step succeeded, with a description of each change.
+    Updated(Vec<String>),
+    /// The step succeeded, but the (remote) system needs a reboot.
+    RebootRequired,
+    /// The step succeeded, but these packages failed to upgrade.
+    PartialFailure(Vec<String>),
     Failure,
     Ignored,
     Skipped(String),
@@ -10,10 +23,53 @@ pub enum StepResult {
 impl StepResult {
     pub fn failed(&self) -> bool {
         match self {
-            StepResult::Success | StepResult::Ignored | StepResult::Skipped(_) => false,
+            StepResult::Success
+            | StepResult::NoChanges
+            | StepResult::Updated(_)
+            | StepResult::RebootRequired
+            | StepResult::PartialFailure(_)
+            | StepResult::Ignored
+            | StepResult::Skipped(_) => false,
             StepResult::Failure => true,
         }
     }
+
+    /// Whether the result is printed in the summary with the given filter.
+    pub fn shown_with(&self, filter: SummaryFilter) -> bool {
+        match filter {
+            SummaryFilter::All => true,
+            SummaryFilter::Changed => !matches!(self, StepResult::NoChanges),
+            SummaryFilter::Problems => {
+                matches!(
+                    self,
+                    StepResult::PartialFailure(_) | StepResult::Failure | StepResult::Ignored | StepResult::Skipped(_)
+                )
+            }
+        }
+    }
+}
+
+impl Display for StepResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StepResult::Success => write!(f, "{}", t!("OK")),
+            StepResult::NoChanges => write!(f, "{}", t!("NO CHANGES")),
+            StepResult::Updated(changes) => {
+                write!(f, "{}", t!("OK"))?;
+                changes.iter().try_for_each(|change| write!(f, "\n    {change}"))
+            }
+            StepResult::RebootRequired => write!(f, "{} ({})", t!("OK"), t!("Reboot required")),
+            StepResult::PartialFailure(packages) => write!(
+                f,
+                "{} ({})",
+                t!("OK"),
+                t!("Failed to upgrade: {packages}", packages = packages.join(", "))
+            ),
+            StepResult::Failure => write!(f, "{}", t!("FAILED")),
+            StepResult::Ignored => write!(f, "{}", t!("IGNORED")),
+            StepResult::Skipped(reason) => write!(f, "{}: {}", t!("SKIPPED"), reason),
+        }
+    }
 }
 
 type CowString<'a> = Cow<'a, str>;
@@ -42,4 +98,96 @@ impl<'a> Report<'a> {
     pub fn data(&self) -> &ReportData<'a> {
         &self.data
     }
+
+    /// The skipped steps, grouped by the reason they were skipped for.
+    pub fn skipped_by_reason(&self) -> Vec<(&str, Vec<&str>)> {
+        let mut skipped: Vec<(&str, Vec<&str>)> = Vec::new();
+        for (key, result) in &self.data {
+            let StepResult::Skipped(reason) = result else {
+                continue;
+            };
+            match skipped.iter_mut().find(|(r, _)| r == reason) {
+                Some((_, keys)) => keys.push(key),
+                None => skipped.push((reason, vec![key])),
+            }
+        }
+        skipped
+    }
+}
+
+/// How to enable a step skipped for `reason`, for the common reasons.
+pub fn skip_hint(reason: &str) -> Option<String> {
+    let set = |option: &str, section: &str| {
+        Some(
+            t!(
+                "Set {option} in the [{section}] section of the configuration",
+                option = option,
+                section = section
+            )
+            .to_string(),
+        )
+    };
+
+    match reason {
+        "Pipupgrade is disabled by default" => set("enable_pipupgrade = true", "python"),
+        "Pip-review is disabled by default" => set("enable_pip_review = true", "python"),
+        "Pip-review (local) is disabled by default" => set("enable_pip_review_local = true", "python"),
+        "tlmgr must be explicity enabled in the configuration to run in Android/Linux" => {
+            set("enable_tlmgr = true", "linux")
+        }
+        _ if reason == t!("Go toolchain updates are not enabled in the configuration") => {
+            set("update_toolchain = true", "go")
+        }
+        _ if reason == t!("Go cache cleanup is not enabled in the configuration") => set("clean_cache = true", "go"),
+        _ if reason == t!("No virtualenvs configured") => set("virtualenvs", "python"),
+        _ if reason == t!("No repositories to pull") => set("repos or search_roots", "git"),
+        _ if reason == t!("Download-only mode is not supported by this step") => set("download_only = false", "misc"),
+        _ if reason == t!("Security-only mode is not supported by this step") => set("security_only = false", "linux"),
+        _ if matches_message(reason, |binary| {
+            t!("Cannot find {binary_name} in PATH", binary_name = binary)
+        }) =>
+        {
+            Some(t!("Install it, or add its directory to PATH").to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Whether `reason` is the message built by `message` with some parameter.
+fn matches_message(reason: &str, message: impl Fn(&str) -> Cow<'_, str>) -> bool {
+    let template = message("\0");
+    let (prefix, suffix) = template.split_once('\0').unwrap_or((&template, ""));
+    reason.len() > prefix.len() + suffix.len() && reason.starts_with(prefix) && reason.ends_with(suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skip_hint() {
+        assert_eq!(
+            skip_hint("Pipupgrade is disabled by default").as_deref(),
+            Some("Set enable_pipupgrade = true in the [python] section of the configuration")
+        );
+        assert_eq!(
+            skip_hint("No repositories to pull").as_deref(),
+            Some("Set repos or search_roots in the [git] section of the configuration")
+        );
+        assert_eq!(
+            skip_hint("Cannot find \"pip3\" in PATH").as_deref(),
+            Some("Install it, or add its directory to PATH")
+        );
+        assert_eq!(skip_hint("Snapd socket does not exist"), None);
+    }
+
+    #[test]
+    fn test_matches_message() {
+        fn message(binary: &str) -> Cow<'_, str> {
+            t!("Cannot find {binary_name} in PATH", binary_name = binary)
+        }
+        assert!(matches_message("Cannot find \"yarn\" in PATH", message));
+        assert!(!matches_message("Cannot find  in PATH", message));
+        assert!(!matches_message("Cannot find \"yarn\"", message));
+    }
 }
@@ -1,17 +1,30 @@
+use crate::command::CommandExt;
 use crate::ctrlc;
-use crate::error::{DryRun, SkipStep};
+use crate::error::{DryRun, NoChanges, PartialFailure, RebootRequired, SkipStep, Updated};
 use crate::execution_context::ExecutionContext;
+use crate::executor;
 use crate::report::{Report, StepResult};
-use crate::terminal::print_error;
+use crate::steps::generic;
+use crate::terminal::{self, print_error, print_result, print_warning};
 use crate::{config::Step, terminal::should_retry};
+use chrono::Local;
 use color_eyre::eyre::Result;
+use rust_i18n::t;
 use std::borrow::Cow;
-use std::fmt::Debug;
+use std::fmt::{Debug, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 use tracing::debug;
 
 pub struct Runner<'a> {
     ctx: &'a ExecutionContext<'a>,
     report: Report<'a>,
+    /// The directory of this run's logs, see `misc.log_dir`.
+    log_dir: Option<PathBuf>,
 }
 
 impl<'a> Runner<'a> {
@@ -19,6 +32,10 @@ impl<'a> Runner<'a> {
         Runner {
             ctx,
             report: Report::new(),
+            log_dir: ctx
+                .config()
+                .log_dir()
+                .map(|dir| dir.join(Local::now().format("%Y-%m-%d_%H-%M-%S").to_string())),
         }
     }
 
@@ -31,30 +48,139 @@ impl<'a> Runner<'a> {
             return Ok(());
         }
 
+        if self.ctx.config().fail_fast() && self.report.data().iter().any(|(_, result)| result.failed()) {
+            debug!("Not running {:?} because a previous step failed", key);
+            return Ok(());
+        }
+
+        // With `misc.interrupt_mode = "graceful"`, the interruption flag stays set after
+        // the step that was running when it was interrupted
+        if ctrlc::interrupted() && !ctrlc::immediate() {
+            debug!("Not running {:?} because Topgrade was interrupted", key);
+            return Ok(());
+        }
+
         let key = key.into();
         debug!("Step {:?}", key);
 
+        if self.ctx.config().download_only() && !step.supports_download_only() {
+            if self.ctx.config().verbose() || self.ctx.config().show_skipped() || self.ctx.config().explain_skips() {
+                self.report.push_result(Some((
+                    key,
+                    StepResult::Skipped(t!("Download-only mode is not supported by this step").to_string()),
+                )));
+            }
+            return Ok(());
+        }
+
         // alter the `func` to put it in a span
+        let ctx = self.ctx;
         let func = || {
             let span =
                 tracing::span!(parent: tracing::Span::none(), tracing::Level::TRACE, "step", step = ?step, key = %key);
             let _guard = span.enter();
-            func()
+            let result = func();
+
+            let succeeded = match &result {
+                Ok(()) => true,
+                Err(e) => is_success_signal(e),
+            };
+            if let Some(command) = ctx.config().post_step_command().filter(|_| succeeded) {
+                generic::custom_command_executor(command, ctx)
+                    .env("TOPGRADE_STEP", key.as_ref())
+                    .status_checked()?;
+            }
+            result
         };
 
+        let log = self.open_step_log(&key);
+        // Matching `misc.ignore_failure_patterns` needs a copy of the output of the step
+        let pattern = self.ctx.config().ignore_failure_pattern(step);
+        let mut output_copy = None;
+        if pattern.is_some() && log.is_none() {
+            match tempfile::tempfile() {
+                Ok(file) => {
+                    executor::set_step_log(file.try_clone().ok());
+                    output_copy = Some(file);
+                }
+                Err(e) => debug!("Could not copy the output of {:?}: {}", key, e),
+            }
+        }
+        let quiet = self.ctx.config().quiet();
+        let timeout = self.ctx.config().step_timeout();
+        let step_key = key.clone();
+
         loop {
-            match func() {
+            if quiet {
+                terminal::set_buffering(true);
+            }
+            let (result, timed_out) = run_with_timeout(&func, timeout);
+            if quiet {
+                // Only the output of the steps with problems is shown
+                let output = terminal::take_buffer();
+                if result
+                    .as_ref()
+                    .is_err_and(|e| !(is_success_signal(e) || e.is::<DryRun>()))
+                {
+                    terminal::write_output(&output, false).ok();
+                }
+            }
+
+            match result {
                 Ok(()) => {
                     self.report.push_result(Some((key, StepResult::Success)));
                     break;
                 }
                 Err(e) if e.downcast_ref::<DryRun>().is_some() => break,
+                Err(e) if e.downcast_ref::<NoChanges>().is_some() => {
+                    self.report.push_result(Some((key, StepResult::NoChanges)));
+                    break;
+                }
+                Err(e) if e.downcast_ref::<Updated>().is_some() => {
+                    let changes = e.downcast::<Updated>().expect("checked above").0;
+                    self.report.push_result(Some((key, StepResult::Updated(changes))));
+                    break;
+                }
+                Err(e) if e.downcast_ref::<RebootRequired>().is_some() => {
+                    self.report.push_result(Some((key, StepResult::RebootRequired)));
+                    break;
+                }
+                Err(e) if e.downcast_ref::<PartialFailure>().is_some() => {
+                    let packages = e.downcast::<PartialFailure>().expect("checked above").0;
+                    self.report
+                        .push_result(Some((key, StepResult::PartialFailure(packages))));
+                    break;
+                }
                 Err(e) if e.downcast_ref::<SkipStep>().is_some() => {
-                    if self.ctx.config().verbose() || self.ctx.config().show_skipped() {
+                    if self.ctx.config().verbose()
+                        || self.ctx.config().show_skipped()
+                        || self.ctx.config().explain_skips()
+                    {
                         self.report.push_result(Some((key, StepResult::Skipped(e.to_string()))));
                     }
                     break;
                 }
+                Err(e) if timed_out => {
+                    debug!("Step {:?} timed out: {:?}", key, e);
+                    let timeout = timeout.expect("timed out without a timeout");
+                    print_warning(t!(
+                        "{step} timed out after {timeout}",
+                        step = key,
+                        timeout = format!("{timeout:?}")
+                    ));
+                    let result = if self.ctx.config().ignore_failure(step) {
+                        StepResult::Ignored
+                    } else {
+                        StepResult::Failure
+                    };
+                    self.report.push_result(Some((key, result)));
+                    break;
+                }
+                Err(e) if ctrlc::interrupted() && !ctrlc::immediate() => {
+                    debug!("Step {:?} failed: {:?}", key, e);
+                    self.report.push_result(Some((key, StepResult::Failure)));
+                    break;
+                }
                 Err(e) => {
                     debug!("Step {:?} failed: {:?}", key, e);
                     let interrupted = ctrlc::interrupted();
@@ -62,7 +188,11 @@ impl<'a> Runner<'a> {
                         ctrlc::unset_interrupted();
                     }
 
-                    let ignore_failure = self.ctx.config().ignore_failure(step);
+                    let ignore_failure = self.ctx.config().ignore_failure(step)
+                        || pattern.as_ref().is_some_and(|pattern| {
+                            let output = step_output(log.as_deref(), output_copy.as_mut());
+                            pattern.is_match(&output) || pattern.is_match(&format!("{e:#}"))
+                        });
                     let should_ask = interrupted || !(self.ctx.config().no_retry() || ignore_failure);
                     let should_retry = if should_ask {
                         print_error(&key, format!("{e:?}"));
@@ -72,6 +202,9 @@ impl<'a> Runner<'a> {
                     };
 
                     if !should_retry {
+                        if !ignore_failure && self.ctx.config().fail_fast() {
+                            print_warning(t!("Skipping the remaining steps because of --fail-fast"));
+                        }
                         self.report.push_result(Some((
                             key,
                             if ignore_failure {
@@ -86,10 +219,131 @@ impl<'a> Runner<'a> {
             }
         }
 
+        if ctrlc::interrupted() && !ctrlc::immediate() {
+            print_warning(t!(
+                "Interrupted, skipping the remaining steps. Press ctrl-c again to exit now"
+            ));
+        }
+
+        if quiet {
+            if let Some((key, result)) = self.report.data().last().filter(|(key, _)| *key == step_key) {
+                if !matches!(
+                    result,
+                    StepResult::Failure | StepResult::Ignored | StepResult::Skipped(_)
+                ) {
+                    print_result(key, result);
+                }
+            }
+        }
+
+        if output_copy.is_some() {
+            executor::set_step_log(None);
+        }
+        if let Some(log) = log {
+            executor::set_step_log(None);
+            // Don't keep the logs of the steps that didn't run anything
+            if fs::metadata(&log).is_ok_and(|metadata| metadata.len() == 0) {
+                fs::remove_file(&log).ok();
+            }
+        }
+
         Ok(())
     }
 
+    /// Start copying the output of the step to its log file, if `misc.log_dir` is set.
+    fn open_step_log(&self, key: &str) -> Option<PathBuf> {
+        let dir = self.log_dir.as_ref()?;
+        let name: String = key
+            .chars()
+            .map(|c| if c.is_alphanumeric() || c == '-' { c } else { '_' })
+            .collect();
+        let path = dir.join(format!("{name}.log"));
+
+        match fs::create_dir_all(dir).and_then(|()| OpenOptions::new().create(true).append(true).open(&path)) {
+            Ok(log) => {
+                executor::set_step_log(Some(log));
+                Some(path)
+            }
+            Err(e) => {
+                print_warning(t!(
+                    "Could not write the log {path}: {error}",
+                    path = path.display(),
+                    error = e
+                ));
+                None
+            }
+        }
+    }
+
+    /// Write the results of all the steps to `summary.log`, if `misc.log_dir` is set.
+    pub fn write_summary_log(&self) {
+        let Some(dir) = &self.log_dir else {
+            return;
+        };
+
+        let mut summary = String::new();
+        for (key, result) in self.report.data() {
+            writeln!(summary, "{key}: {result}").ok();
+        }
+
+        let path = dir.join("summary.log");
+        if let Err(e) = fs::create_dir_all(dir).and_then(|()| fs::write(&path, summary)) {
+            print_warning(t!(
+                "Could not write the log {path}: {error}",
+                path = path.display(),
+                error = e
+            ));
+        }
+    }
+
     pub fn report(&self) -> &Report {
         &self.report
     }
 }
+
+/// Run `func`, stopping the commands it runs once `timeout` has passed.
+/// Also returns whether it timed out.
+fn run_with_timeout(func: &impl Fn() -> Result<()>, timeout: Option<Duration>) -> (Result<()>, bool) {
+    let Some(timeout) = timeout else {
+        return (func(), false);
+    };
+
+    let (done, finished) = mpsc::channel::<()>();
+    let watchdog = thread::spawn(move || {
+        let mut wait = timeout;
+        let mut timed_out = false;
+        // Keep stopping the commands of the step until it gives up
+        while let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(wait) {
+            timed_out = true;
+            ctrlc::kill_running_child();
+            wait = Duration::from_secs(1);
+        }
+        timed_out
+    });
+
+    let result = func();
+    drop(done);
+    let timed_out = watchdog.join().expect("step watchdog panicked");
+    (result, timed_out)
+}
+
+/// Whether the error is one of the signals of a successful step, like `NoChanges`.
+fn is_success_signal(e: &color_eyre::Report) -> bool {
+    e.is::<NoChanges>() || e.is::<Updated>() || e.is::<RebootRequired>() || e.is::<PartialFailure>()
+}
+
+/// The output of the step so far, from its log file or the copy made for it.
+fn step_output(log: Option<&Path>, copy: Option<&mut File>) -> String {
+    let mut output = Vec::new();
+    let read = match (log, copy) {
+        (Some(log), _) => File::open(log).and_then(|mut log| log.read_to_end(&mut output)),
+        (None, Some(copy)) => copy
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| copy.read_to_end(&mut output)),
+        (None, None) => Ok(0),
+    };
+    if let Err(e) = read {
+        debug!("Could not read the output of the step: {e}");
+    }
+    String::from_utf8_lossy(&output).into_owned()
+}
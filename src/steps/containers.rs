@@ -11,6 +11,7 @@ use wildmatch::WildMatch;
 use crate::command::CommandExt;
 use crate::error::{self, TopgradeError};
 use crate::terminal::print_separator;
+use crate::utils::{which, PathExt};
 use crate::{execution_context::ExecutionContext, utils::require};
 use rust_i18n::t;
 
@@ -194,3 +195,41 @@ pub fn run_containers(ctx: &ExecutionContext) -> Result<()> {
         Err(eyre!(error::StepFailed))
     }
 }
+
+/// Pull the images of a compose file, and recreate its containers with `containers.compose_up`.
+pub fn run_compose_update(ctx: &ExecutionContext, file: &Path) -> Result<()> {
+    let container_runtime = ctx.config().containers_runtime().to_string();
+    file.require()?;
+
+    // Compose v2 is a plugin of the runtime, v1 is a separate `docker-compose` binary
+    let compose = match which(&container_runtime) {
+        Some(crt)
+            if Command::new(&crt)
+                .args(["compose", "version"])
+                .output_checked_utf8()
+                .is_ok() =>
+        {
+            vec![crt.into_os_string(), "compose".into()]
+        }
+        _ => vec![require(format!("{container_runtime}-compose"))?.into_os_string()],
+    };
+
+    print_separator(format!("Compose ({})", file.display()));
+
+    let run = |args: &[&str]| {
+        ctx.run_type()
+            .execute(&compose[0])
+            .args(&compose[1..])
+            .arg("-f")
+            .arg(file)
+            .args(args)
+            .status_checked()
+    };
+
+    run(&["pull"])?;
+    if ctx.config().compose_up() {
+        run(&["up", "-d"])?;
+    }
+
+    Ok(())
+}
@@ -1,8 +1,9 @@
 #![allow(unused_imports)]
 
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::path::PathBuf;
 use std::process::Command;
+use std::sync::OnceLock;
 use std::{env, path::Path};
 use std::{fs, io::Write};
 
@@ -16,7 +17,7 @@ use tracing::{debug, error};
 
 use crate::command::{CommandExt, Utf8Output};
 use crate::execution_context::ExecutionContext;
-use crate::executor::ExecutorOutput;
+use crate::executor::{Executor, ExecutorOutput};
 use crate::terminal::{print_separator, shell};
 use crate::utils::{self, check_is_python_2_or_shim, get_require_sudo_string, require, require_option, which, PathExt};
 use crate::Step;
@@ -92,6 +93,20 @@ pub fn run_cargo_update(ctx: &ExecutionContext) -> Result<()> {
     Ok(())
 }
 
+/// Update the `Cargo.lock` of a project listed in `cargo.projects`.
+pub fn run_cargo_project_update(ctx: &ExecutionContext, project: &Path) -> Result<()> {
+    let cargo = require("cargo")?;
+    project.join("Cargo.toml").require()?;
+
+    print_separator(format!("Cargo ({})", project.display()));
+
+    ctx.run_type()
+        .execute(cargo)
+        .arg("update")
+        .current_dir(project)
+        .status_checked()
+}
+
 pub fn run_flutter_upgrade(ctx: &ExecutionContext) -> Result<()> {
     let flutter = require("flutter")?;
 
@@ -244,7 +259,12 @@ pub fn run_rustup(ctx: &ExecutionContext) -> Result<()> {
     let rustup = require("rustup")?;
 
     print_separator("rustup");
-    ctx.run_type().execute(rustup).arg("update").status_checked()
+    let mut command = ctx.run_type().execute(rustup);
+    command.arg("update");
+    if let Some(toolchains) = ctx.config().rustup_toolchains() {
+        command.args(toolchains);
+    }
+    command.status_checked()
 }
 
 pub fn run_rye(ctx: &ExecutionContext) -> Result<()> {
@@ -331,10 +351,19 @@ pub fn run_gcloud_components_update(ctx: &ExecutionContext) -> Result<()> {
     } else {
         print_separator("gcloud");
 
-        ctx.run_type()
-            .execute(gcloud)
-            .args(["components", "update", "--quiet"])
-            .status_checked()
+        if ctx.config().gcloud_include_preview() {
+            ctx.run_type()
+                .execute(&gcloud)
+                .args(["components", "install", "alpha", "beta", "--quiet"])
+                .status_checked()?;
+        }
+
+        let mut command = ctx.run_type().execute(&gcloud);
+        command.args(["components", "update", "--quiet"]);
+        if let Some(components) = ctx.config().gcloud_components() {
+            command.args(components);
+        }
+        command.status_checked()
     }
 }
 
@@ -506,14 +535,55 @@ pub fn run_conda_update(ctx: &ExecutionContext) -> Result<()> {
         return Err(SkipStep("auto_activate_base is set to False".to_string()).into());
     }
 
+    let environments = conda_environments(ctx, &conda)?;
+
     print_separator("Conda");
 
-    let mut command = ctx.run_type().execute(conda);
-    command.args(["update", "--all", "-n", "base"]);
-    if ctx.config().yes(Step::Conda) {
-        command.arg("--yes");
+    for (flag, environment) in environments {
+        let mut command = ctx.run_type().execute(&conda);
+        command.args(["update", "--all", flag, &environment]);
+        if ctx.config().yes(Step::Conda) {
+            command.arg("--yes");
+        }
+        command.status_checked()?;
     }
-    command.status_checked()
+
+    Ok(())
+}
+
+/// The environments to update with `conda` or `mamba`, as `-n NAME` or `-p PATH` arguments.
+fn conda_environments(ctx: &ExecutionContext, conda: &Path) -> Result<Vec<(&'static str, String)>> {
+    let envs = ctx.config().conda_envs().filter(|envs| !envs.is_empty());
+
+    if ctx.config().conda_all_envs() {
+        if envs.is_some() {
+            return Err(eyre!(
+                "{}",
+                t!("`conda.all_envs` and `conda.envs` cannot be used together")
+            ));
+        }
+
+        #[derive(serde::Deserialize)]
+        struct EnvList {
+            envs: Vec<String>,
+        }
+
+        let output = Command::new(conda)
+            .args(["env", "list", "--json"])
+            .output_checked_utf8()?;
+        let list: EnvList = serde_json::from_str(&output.stdout)?;
+        return Ok(list.envs.into_iter().map(|path| ("-p", path)).collect());
+    }
+
+    let mut environments = vec![("-n", String::from("base"))];
+    for env in envs.into_iter().flatten() {
+        if env.contains(['/', '\\']) {
+            environments.push(("-p", shellexpand::tilde(env).into_owned()));
+        } else {
+            environments.push(("-n", env.clone()));
+        }
+    }
+    Ok(environments)
 }
 
 pub fn run_pixi_update(ctx: &ExecutionContext) -> Result<()> {
@@ -526,14 +596,20 @@ pub fn run_pixi_update(ctx: &ExecutionContext) -> Result<()> {
 pub fn run_mamba_update(ctx: &ExecutionContext) -> Result<()> {
     let mamba = require("mamba")?;
 
+    let environments = conda_environments(ctx, &mamba)?;
+
     print_separator("Mamba");
 
-    let mut command = ctx.run_type().execute(mamba);
-    command.args(["update", "--all", "-n", "base"]);
-    if ctx.config().yes(Step::Mamba) {
-        command.arg("--yes");
+    for (flag, environment) in environments {
+        let mut command = ctx.run_type().execute(&mamba);
+        command.args(["update", "--all", flag, &environment]);
+        if ctx.config().yes(Step::Mamba) {
+            command.arg("--yes");
+        }
+        command.status_checked()?;
     }
-    command.status_checked()
+
+    Ok(())
 }
 
 pub fn run_miktex_packages_update(ctx: &ExecutionContext) -> Result<()> {
@@ -547,6 +623,29 @@ pub fn run_miktex_packages_update(ctx: &ExecutionContext) -> Result<()> {
 }
 
 pub fn run_pip3_update(ctx: &ExecutionContext) -> Result<()> {
+    let interpreters = ctx.config().python_interpreters();
+    if !interpreters.is_empty() {
+        print_separator("pip3");
+        check_not_in_virtual_env()?;
+
+        for python3 in interpreters {
+            match check_pip3_updatable(&python3) {
+                Err(e) if e.is::<SkipStep>() => {
+                    println!(
+                        "{}",
+                        t!("Skipping {python}: {reason}", python = python3.display(), reason = e)
+                    );
+                }
+                result => {
+                    result?;
+                    update_pip3(ctx, &python3)?;
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
     let py = require("python").and_then(check_is_python_2_or_shim);
     let py3 = require("python3").and_then(check_is_python_2_or_shim);
 
@@ -559,13 +658,23 @@ pub fn run_pip3_update(ctx: &ExecutionContext) -> Result<()> {
         }
     };
 
-    Command::new(&python3)
+    check_pip3_updatable(&python3)?;
+
+    print_separator("pip3");
+    check_not_in_virtual_env()?;
+
+    update_pip3(ctx, &python3)
+}
+
+/// Skip interpreters without pip, or whose packages are managed externally.
+fn check_pip3_updatable(python3: &Path) -> Result<()> {
+    Command::new(python3)
         .args(["-m", "pip"])
         .output_checked_utf8()
         .map_err(|_| SkipStep("pip does not exist".to_string()))?;
 
     let check_extern_managed_script = "import sysconfig; from os import path; print('Y') if path.isfile(path.join(sysconfig.get_path('stdlib'), 'EXTERNALLY-MANAGED')) else print('N')";
-    let output = Command::new(&python3)
+    let output = Command::new(python3)
         .args(["-c", check_extern_managed_script])
         .output_checked_utf8()?;
     let stdout = output.stdout.trim();
@@ -575,7 +684,7 @@ pub fn run_pip3_update(ctx: &ExecutionContext) -> Result<()> {
         _ => unreachable!("unexpected output from `check_extern_managed_script`"),
     };
 
-    let allow_break_sys_pkg = match Command::new(&python3)
+    let allow_break_sys_pkg = match Command::new(python3)
         .args(["-m", "pip", "config", "get", "global.break-system-packages"])
         .output_checked_utf8()
     {
@@ -612,16 +721,94 @@ pub fn run_pip3_update(ctx: &ExecutionContext) -> Result<()> {
         .into());
     }
 
-    print_separator("pip3");
+    Ok(())
+}
+
+fn check_not_in_virtual_env() -> Result<()> {
     if env::var("VIRTUAL_ENV").is_ok() {
         print_warning("This step is skipped when running inside a virtual environment");
         return Err(SkipStep("Does not run inside a virtual environment".to_string()).into());
     }
 
+    Ok(())
+}
+
+fn update_pip3(ctx: &ExecutionContext, python3: &Path) -> Result<()> {
     ctx.run_type()
-        .execute(&python3)
+        .execute(python3)
         .args(["-m", "pip", "install", "--upgrade", "--user", "pip"])
-        .status_checked()
+        .status_checked()?;
+
+    clean_cache(ctx, python3, &["-m", "pip", "cache"], "purge")
+}
+
+/// Clean the cache of a Python tool when cleaning up, and report the reclaimed space.
+///
+/// `cache` is the tool's cache subcommand, which prints the cache directory with `dir`
+/// and cleans it with `clean`. Tools without it, or with the cache disabled, are left alone.
+fn clean_cache(ctx: &ExecutionContext, program: &Path, cache: &[&str], clean: &str) -> Result<()> {
+    if !ctx.config().cleanup() {
+        return Ok(());
+    }
+
+    let dir = match Command::new(program).args(cache).arg("dir").output_checked_utf8() {
+        Ok(output) => PathBuf::from(output.stdout.trim()),
+        Err(e) => {
+            debug!("Not cleaning the cache of {}: {e}", program.display());
+            return Ok(());
+        }
+    };
+    let before = utils::dir_size(&dir);
+
+    // The packages are already upgraded, a failed cleanup doesn't fail the step
+    let result = ctx.run_type().execute(program).args(cache).arg(clean).status_checked();
+    if let Err(e) = result {
+        print_warning(t!(
+            "Could not clean the cache of {program}: {error}",
+            program = program.display(),
+            error = e
+        ));
+    } else if !ctx.run_type().dry() {
+        utils::print_reclaimed(before.saturating_sub(utils::dir_size(&dir)));
+    }
+
+    Ok(())
+}
+
+/// Update pip in each virtualenv of `python.virtualenvs`.
+pub fn run_pip_virtualenvs_update(ctx: &ExecutionContext) -> Result<()> {
+    let virtualenvs = ctx.config().python_virtualenvs();
+    if virtualenvs.is_empty() {
+        return Err(SkipStep(t!("No virtualenvs configured").to_string()).into());
+    }
+
+    print_separator("pip (virtualenvs)");
+
+    for venv in virtualenvs {
+        if !venv.join("pyvenv.cfg").is_file() {
+            println!("{}", t!("Skipping {venv}: not a virtualenv", venv = venv.display()));
+            continue;
+        }
+
+        // On Windows, pip can't replace its own executable, so it has to run as a module
+        #[cfg(windows)]
+        let (pip, args): (_, &[&str]) = (venv.join("Scripts").join("python.exe"), &["-m", "pip"]);
+        #[cfg(not(windows))]
+        let (pip, args): (_, &[&str]) = (venv.join("bin").join("pip"), &[]);
+
+        if !pip.exists() {
+            println!("{}", t!("Skipping {venv}: pip does not exist", venv = venv.display()));
+            continue;
+        }
+
+        ctx.run_type()
+            .execute(pip)
+            .args(args)
+            .args(["install", "--upgrade", "pip"])
+            .status_checked()?;
+    }
+
+    Ok(())
 }
 
 pub fn run_pip_review_update(ctx: &ExecutionContext) -> Result<()> {
@@ -715,7 +902,7 @@ pub fn run_tlmgr_update(ctx: &ExecutionContext) -> Result<()> {
     let kpsewhich = require("kpsewhich")?;
     let tlmgr_directory = {
         let mut d = PathBuf::from(
-            &Command::new(kpsewhich)
+            &Command::new(&kpsewhich)
                 .arg("-var-value=SELFAUTOPARENT")
                 .output_checked_utf8()?
                 .stdout
@@ -729,8 +916,24 @@ pub fn run_tlmgr_update(ctx: &ExecutionContext) -> Result<()> {
     let directory_writable = tempfile_in(&tlmgr_directory).is_ok();
     debug!("{:?} writable: {}", tlmgr_directory, directory_writable);
 
+    // A user tree is set up with `tlmgr init-usertree`
+    let user_tree = Command::new(&kpsewhich)
+        .arg("-var-value=TEXMFHOME")
+        .output_checked_utf8()
+        .map(|output| PathBuf::from(output.stdout.trim()).join("tlpkg/texlive.tlpdb").exists())
+        .unwrap_or(false);
+    debug!("User TeX tree exists: {}", user_tree);
+
     print_separator("TeX Live package manager");
 
+    if ctx.config().tlmgr_user_mode() || (user_tree && !directory_writable) {
+        return ctx
+            .run_type()
+            .execute(&tlmgr)
+            .args(["--usermode", "update", "--all"])
+            .status_checked();
+    }
+
     let mut command = if directory_writable {
         ctx.run_type().execute(&tlmgr)
     } else {
@@ -750,6 +953,11 @@ pub fn run_chezmoi_update(ctx: &ExecutionContext) -> Result<()> {
 
     print_separator("chezmoi");
 
+    if ctx.run_type().dry() {
+        // `chezmoi diff` doesn't modify anything, so actually run it to preview the pending changes
+        return Command::new(chezmoi).arg("diff").status_checked();
+    }
+
     ctx.run_type().execute(chezmoi).arg("update").status_checked()
 }
 
@@ -775,7 +983,71 @@ pub fn run_myrepos_update(ctx: &ExecutionContext) -> Result<()> {
 
 pub fn run_custom_command(name: &str, command: &str, ctx: &ExecutionContext) -> Result<()> {
     print_separator(name);
-    let mut exec = ctx.run_type().execute(shell());
+    custom_command_executor(command, ctx).status_checked()
+}
+
+/// The executable files in `misc.hooks_dir`, with their file names, sorted by name.
+pub fn hooks(dir: &Path) -> Vec<(String, PathBuf)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            print_warning(t!(
+                "Could not read the hooks directory {directory}: {error}",
+                directory = dir.display(),
+                error = e
+            ));
+            return Vec::new();
+        }
+    };
+
+    let mut hooks: Vec<(String, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.metadata().is_ok_and(|metadata| is_executable(&metadata)))
+        .map(|entry| (entry.file_name().to_string_lossy().into_owned(), entry.path()))
+        .collect();
+    hooks.sort();
+    debug!("Hooks in {}: {hooks:?}", dir.display());
+
+    hooks
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(windows)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    metadata.is_file()
+}
+
+pub fn run_hook(name: &str, hook: &Path, ctx: &ExecutionContext) -> Result<()> {
+    print_separator(name);
+    ctx.run_type().execute(hook).status_checked()
+}
+
+/// The shell running the custom commands, resolved once so that a missing
+/// `misc.custom_command_shell` is only reported once.
+static CUSTOM_COMMAND_SHELL: OnceLock<OsString> = OnceLock::new();
+
+/// Build an `Executor` running `command` in the custom command shell.
+pub fn custom_command_executor(command: &str, ctx: &ExecutionContext) -> Executor {
+    let shell = CUSTOM_COMMAND_SHELL.get_or_init(|| match ctx.config().custom_command_shell() {
+        Some(custom_shell) => match which(custom_shell) {
+            Some(path) => path.into_os_string(),
+            None => {
+                print_warning(t!(
+                    "Custom command shell {shell} not found, falling back to the default shell",
+                    shell = custom_shell
+                ));
+                shell().into()
+            }
+        },
+        None => shell().into(),
+    });
+    let mut exec = ctx.run_type().execute(shell);
     #[cfg(unix)]
     let command = if let Some(command) = command.strip_prefix("-i ") {
         exec.arg("-i");
@@ -783,7 +1055,8 @@ pub fn run_custom_command(name: &str, command: &str, ctx: &ExecutionContext) ->
     } else {
         command
     };
-    exec.arg("-c").arg(command).status_checked()
+    exec.arg("-c").arg(command);
+    exec
 }
 
 pub fn run_composer_update(ctx: &ExecutionContext) -> Result<()> {
@@ -1202,7 +1475,9 @@ pub fn run_uv(ctx: &ExecutionContext) -> Result<()> {
     ctx.run_type()
         .execute(&uv_exec)
         .args(["tool", "upgrade", "--all"])
-        .status_checked()
+        .status_checked()?;
+
+    clean_cache(ctx, &uv_exec, &["cache"], "prune")
 }
 
 /// Involve `zvm upgrade` to update ZVM
@@ -1,4 +1,5 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Output, Stdio};
@@ -16,9 +17,13 @@ use crate::command::CommandExt;
 use crate::config::Step;
 use crate::execution_context::ExecutionContext;
 use crate::steps::emacs::Emacs;
-use crate::terminal::print_separator;
-use crate::utils::{require, PathExt};
-use crate::{error::SkipStep, terminal::print_warning, HOME_DIR};
+use crate::terminal::{print_separator, shell};
+use crate::utils::{require, which, PathExt};
+use crate::{
+    error::{NoChanges, SkipStep, Updated},
+    terminal::print_warning,
+    HOME_DIR,
+};
 use etcetera::base_strategy::BaseStrategy;
 use rust_i18n::t;
 
@@ -92,10 +97,14 @@ pub fn run_git_pull(ctx: &ExecutionContext) -> Result<()> {
     // Handle user-defined repos
     if let Some(custom_git_repos) = config.git_repos() {
         for git_repo in custom_git_repos {
-            repos.glob_insert(git_repo);
+            repos.glob_insert(git_repo.path(), git_repo.expected_branch(), git_repo.command());
         }
     }
 
+    for root in config.git_search_roots() {
+        repos.discover(&root, config.git_search_depth());
+    }
+
     // Warn the user about the bad patterns.
     //
     // NOTE: this should be executed **before** skipping the Git step or the
@@ -123,6 +132,8 @@ static PATH_PREFIX: &str = "\\\\?\\";
 pub struct RepoStep {
     git: PathBuf,
     repos: HashSet<PathBuf>,
+    expected_branches: HashMap<PathBuf, String>,
+    pull_commands: HashMap<PathBuf, String>,
     glob_match_options: MatchOptions,
     bad_patterns: Vec<String>,
 }
@@ -138,6 +149,40 @@ fn output_checked_utf8(output: Output) -> Result<()> {
     }
 }
 
+/// Whether the `.gitattributes` of the repository stores files with Git LFS.
+fn uses_lfs(repo: &Path) -> bool {
+    fs::read_to_string(repo.join(".gitattributes")).is_ok_and(|attributes| attributes.contains("filter=lfs"))
+}
+
+/// Warn when the HEAD commit of `repo` doesn't have a good signature, otherwise print its signer.
+fn check_head_signature(git: &Path, repo: &Path) {
+    // `%G?` is `G` for a good signature, `U` for a good one of unknown validity
+    let output = match Command::new(git)
+        .stdin(Stdio::null())
+        .current_dir(repo)
+        .args(["log", "-1", "--format=%G?%n%GS", "HEAD"])
+        .output_checked_utf8()
+    {
+        Ok(output) => output.stdout,
+        Err(e) => {
+            error!("Error checking the signature of {}: {e}", repo.display());
+            return;
+        }
+    };
+    let mut lines = output.lines();
+    let status = lines.next().unwrap_or_default();
+    let signer = lines.next().unwrap_or_default().trim();
+
+    match status {
+        "G" | "U" => println!("{}", t!("Signed by {signer}", signer = signer)),
+        "N" => print_warning(t!("The new HEAD of {repo} is not signed", repo = repo.display())),
+        _ => print_warning(t!(
+            "The signature of the new HEAD of {repo} could not be verified",
+            repo = repo.display()
+        )),
+    }
+}
+
 fn get_head_revision<P: AsRef<Path>>(git: &Path, repo: P) -> Option<String> {
     Command::new(git)
         .stdin(Stdio::null())
@@ -166,6 +211,8 @@ impl RepoStep {
         Ok(Self {
             git,
             repos: HashSet::new(),
+            expected_branches: HashMap::new(),
+            pull_commands: HashMap::new(),
             bad_patterns: Vec::new(),
             glob_match_options,
         })
@@ -245,8 +292,49 @@ impl RepoStep {
             .ok()
     }
 
+    /// Get the branch checked out in `repo`, `None` if HEAD is detached.
+    fn current_branch<P: AsRef<Path>>(&self, repo: P) -> Option<String> {
+        Command::new(&self.git)
+            .stdin(Stdio::null())
+            .current_dir(repo.as_ref())
+            .args(["symbolic-ref", "--short", "-q", "HEAD"])
+            .output_checked_utf8()
+            .ok()
+            .map(|output| output.stdout.trim().to_string())
+    }
+
+    /// Check that `repo` is on a branch, and on the expected one if configured.
+    /// Returns the reason to skip it otherwise.
+    ///
+    /// Repositories with a custom pull command may have a detached HEAD, like the ones
+    /// colocated with jj, unless an expected branch is configured.
+    fn check_branch(&self, repo: &Path) -> Option<String> {
+        let expected = self.expected_branches.get(repo);
+        let Some(branch) = self.current_branch(repo) else {
+            if expected.is_none() && self.pull_commands.contains_key(repo) {
+                return None;
+            }
+            return Some(t!("because HEAD is detached").to_string());
+        };
+
+        match expected {
+            Some(expected) if expected != &branch => Some(
+                t!(
+                    "because it is on branch {branch} instead of {expected}",
+                    branch = branch,
+                    expected = expected
+                )
+                .to_string(),
+            ),
+            _ => None,
+        }
+    }
+
     /// Similar to `insert_if_repo`, with glob support.
-    pub fn glob_insert(&mut self, pattern: &str) {
+    ///
+    /// The repositories found are only pulled when on `expected_branch`, if given,
+    /// and with `pull_command` run by the shell instead of `git pull`, if given.
+    pub fn glob_insert(&mut self, pattern: &str, expected_branch: Option<&str>, pull_command: Option<&str>) {
         if let Ok(glob) = glob_with(pattern, self.glob_match_options) {
             let mut last_git_repo: Option<PathBuf> = None;
             for entry in glob {
@@ -262,7 +350,14 @@ impl RepoStep {
                                 continue;
                             }
                         }
-                        if self.insert_if_repo(&path) {
+                        if let Some(repo) = self.get_repo_root(&path) {
+                            if let Some(branch) = expected_branch {
+                                self.expected_branches.insert(repo.clone(), branch.to_string());
+                            }
+                            if let Some(command) = pull_command {
+                                self.pull_commands.insert(repo.clone(), command.to_string());
+                            }
+                            self.repos.insert(repo);
                             last_git_repo = Some(path);
                         }
                     }
@@ -280,6 +375,35 @@ impl RepoStep {
         }
     }
 
+    /// Search `dir` for git repositories, up to `depth` directories deep, and add them
+    /// to `self.repos`. Repositories are not searched further, so nested repositories
+    /// such as submodules are skipped.
+    pub fn discover(&mut self, dir: &Path, depth: usize) {
+        if dir.join(".git").exists() {
+            self.insert_if_repo(dir);
+            return;
+        }
+
+        if depth == 0 {
+            return;
+        }
+
+        let entries = match dir.read_dir() {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug!("Could not search {} for git repositories: {e}", dir.display());
+                return;
+            }
+        };
+
+        for entry in entries.flatten() {
+            // Symlinks are not followed to avoid loops
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                self.discover(&entry.path(), depth - 1);
+            }
+        }
+    }
+
     /// True if `self.repos` is empty.
     pub fn is_repos_empty(&self) -> bool {
         self.repos.is_empty()
@@ -294,51 +418,123 @@ impl RepoStep {
         debug_assert!(_removed);
     }
 
-    /// Try to pull a repo.
-    async fn pull_repo<P: AsRef<Path>>(&self, ctx: &ExecutionContext<'_>, repo: P) -> Result<()> {
+    /// Pull a repository, returning how many new commits it got.
+    async fn pull_repo<P: AsRef<Path>>(&self, ctx: &ExecutionContext<'_>, repo: P, lfs: bool) -> Result<usize> {
         let before_revision = get_head_revision(&self.git, &repo);
+        // A merge or rebase the user left unfinished must never be aborted
+        let merging_before = self.merge_in_progress(repo.as_ref()).await;
 
         if ctx.config().verbose() {
             println!("{} {}", style(t!("Pulling")).cyan().bold(), repo.as_ref().display());
         }
 
-        let mut command = AsyncCommand::new(&self.git);
+        let stashed = ctx.config().git_auto_stash() && self.stash_changes(repo.as_ref()).await?;
 
-        command
-            .stdin(Stdio::null())
-            .current_dir(&repo)
-            .args(["pull", "--ff-only"]);
+        let fetch_result = if ctx.config().git_fetch_all_remotes() {
+            self.fetch_all_remotes(ctx, repo.as_ref()).await
+        } else {
+            Ok(())
+        };
+
+        let mut command = match self.pull_commands.get(repo.as_ref()) {
+            Some(pull_command) => {
+                let mut command = AsyncCommand::new(shell());
+                command.arg("-c").arg(pull_command);
+                command
+            }
+            None => {
+                let mut command = AsyncCommand::new(&self.git);
+                command.args(["pull", "--ff-only"]);
+                if let Some(extra_arguments) = ctx.config().git_arguments() {
+                    command.args(extra_arguments.split_whitespace());
+                }
+                command
+            }
+        };
 
-        if let Some(extra_arguments) = ctx.config().git_arguments() {
-            command.args(extra_arguments.split_whitespace());
+        command.stdin(Stdio::null()).current_dir(&repo);
+
+        if !ctx.config().git_allow_prompt() {
+            command.env("GIT_TERMINAL_PROMPT", "0");
         }
 
-        let pull_output = command.output().await?;
-        let submodule_output = AsyncCommand::new(&self.git)
-            .args(["submodule", "update", "--recursive"])
-            .current_dir(&repo)
-            .stdin(Stdio::null())
-            .output()
-            .await?;
-        let result = output_checked_utf8(pull_output)
-            .and_then(|_| output_checked_utf8(submodule_output))
-            .wrap_err_with(|| format!("Failed to pull {}", repo.as_ref().display()));
+        // Nothing after stashing returns early, so that the changes are always restored
+        let pull_output = command.output().await;
+        // Only possible when `git.arguments` allows pulls that aren't fast-forwards
+        let conflict = match &pull_output {
+            Ok(output) => {
+                !output.status.success() && !merging_before && self.has_conflicts(repo.as_ref(), output).await
+            }
+            Err(_) => false,
+        };
+        let mut result = if conflict {
+            self.abort_merge(repo.as_ref()).await;
+            Err(eyre!("{}", t!("merge conflict, aborted")))
+        } else {
+            let submodule_output = AsyncCommand::new(&self.git)
+                .args(["submodule", "update", "--recursive"])
+                .current_dir(&repo)
+                .stdin(Stdio::null())
+                .output()
+                .await;
+            fetch_result
+                .and_then(|()| pull_output.map_err(Into::into).and_then(output_checked_utf8))
+                .and_then(|()| submodule_output.map_err(Into::into).and_then(output_checked_utf8))
+        }
+        .wrap_err_with(|| format!("Failed to pull {}", repo.as_ref().display()));
+
+        if lfs && result.is_ok() && uses_lfs(repo.as_ref()) {
+            let mut command = AsyncCommand::new(&self.git);
+            command.stdin(Stdio::null()).current_dir(&repo).args(["lfs", "pull"]);
+            if !ctx.config().git_allow_prompt() {
+                command.env("GIT_TERMINAL_PROMPT", "0");
+            }
+            result = command
+                .output()
+                .await
+                .map_err(Into::into)
+                .and_then(output_checked_utf8)
+                .wrap_err_with(|| format!("Failed to pull the LFS files of {}", repo.as_ref().display()));
+        }
 
-        if result.is_err() {
+        let mut new_commits = 0;
+        if conflict {
+            println!(
+                "{} {} {}",
+                style(t!("Conflict")).red().bold(),
+                repo.as_ref().display(),
+                t!("merge conflict, aborted. Pull it manually to resolve the conflict")
+            );
+        } else if let Err(e) = &result {
             println!(
                 "{} {} {}",
                 style(t!("Failed")).red().bold(),
                 t!("pulling"),
                 repo.as_ref().display()
             );
+            if e.root_cause().to_string().contains("terminal prompts disabled") {
+                println!(
+                    "{}",
+                    t!("The repository needs credentials. Set git.allow_prompt to be prompted for them")
+                );
+            }
         } else {
             let after_revision = get_head_revision(&self.git, repo.as_ref());
 
             match (&before_revision, &after_revision) {
                 (Some(before), Some(after)) if before != after => {
+                    // Still count the repository as changed if counting fails
+                    new_commits = Command::new(&self.git)
+                        .stdin(Stdio::null())
+                        .current_dir(&repo)
+                        .args(["rev-list", "--count", &format!("{before}..{after}")])
+                        .output_checked_utf8()
+                        .ok()
+                        .and_then(|output| output.stdout.trim().parse().ok())
+                        .unwrap_or(1);
                     println!("{} {}", style(t!("Changed")).yellow().bold(), repo.as_ref().display());
 
-                    Command::new(&self.git)
+                    let log_result = Command::new(&self.git)
                         .stdin(Stdio::null())
                         .current_dir(&repo)
                         .args([
@@ -348,7 +544,13 @@ impl RepoStep {
                             "--oneline",
                             &format!("{before}..{after}"),
                         ])
-                        .status_checked()?;
+                        .status_checked();
+                    if let Err(e) = log_result {
+                        result = Err(e);
+                    }
+                    if ctx.config().git_verify_signatures() {
+                        check_head_signature(&self.git, repo.as_ref());
+                    }
                     println!();
                 }
                 _ => {
@@ -359,7 +561,152 @@ impl RepoStep {
             }
         }
 
-        result.map(|_| ())
+        if stashed {
+            let pop_output = AsyncCommand::new(&self.git)
+                .stdin(Stdio::null())
+                .current_dir(&repo)
+                .args(["stash", "pop"])
+                .output()
+                .await;
+            if let Err(e) = pop_output.map_err(Into::into).and_then(output_checked_utf8) {
+                let message = t!(
+                    "Could not restore the stashed changes of {repo}, they are kept in `git stash list`",
+                    repo = repo.as_ref().display()
+                );
+                println!("{} {}", style(t!("Conflict")).red().bold(), message);
+                result = result.and(Err(e).wrap_err(message));
+            }
+        }
+
+        result.map(|_| new_commits)
+    }
+
+    /// Whether a failed pull left the repository with conflicts, either reported by
+    /// `git pull` or as unmerged files.
+    async fn has_conflicts(&self, repo: &Path, pull_output: &Output) -> bool {
+        String::from_utf8_lossy(&pull_output.stdout).contains("CONFLICT") || self.has_unmerged_files(repo).await
+    }
+
+    /// Whether `git diff --diff-filter=U` lists unmerged files.
+    async fn has_unmerged_files(&self, repo: &Path) -> bool {
+        match AsyncCommand::new(&self.git)
+            .stdin(Stdio::null())
+            .current_dir(repo)
+            .args(["diff", "--name-only", "--diff-filter=U"])
+            .output()
+            .await
+        {
+            Ok(output) => output.status.success() && !output.stdout.is_empty(),
+            Err(e) => {
+                debug!("Could not check {} for conflicts: {e}", repo.display());
+                false
+            }
+        }
+    }
+
+    /// Whether a merge or a rebase is in progress: the repository has unmerged files,
+    /// `MERGE_HEAD` or a rebase directory.
+    async fn merge_in_progress(&self, repo: &Path) -> bool {
+        if self.has_unmerged_files(repo).await {
+            return true;
+        }
+
+        match AsyncCommand::new(&self.git)
+            .stdin(Stdio::null())
+            .current_dir(repo)
+            .args([
+                "rev-parse",
+                "--git-path",
+                "MERGE_HEAD",
+                "--git-path",
+                "rebase-merge",
+                "--git-path",
+                "rebase-apply",
+            ])
+            .output()
+            .await
+        {
+            // The paths are relative to the repository
+            Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .any(|path| repo.join(path.trim()).exists()),
+            Ok(_) => false,
+            Err(e) => {
+                debug!("Could not check {} for a merge in progress: {e}", repo.display());
+                false
+            }
+        }
+    }
+
+    /// Abort the merge, or the rebase when pulling with `--rebase`, to leave the repository clean.
+    async fn abort_merge(&self, repo: &Path) {
+        for operation in ["merge", "rebase"] {
+            let output = AsyncCommand::new(&self.git)
+                .stdin(Stdio::null())
+                .current_dir(repo)
+                .args([operation, "--abort"])
+                .output()
+                .await;
+            match output {
+                Ok(output) if output.status.success() => return,
+                Ok(output) => debug!(
+                    "git {operation} --abort failed in {}: {}",
+                    repo.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+                Err(e) => debug!("Could not run git {operation} --abort in {}: {e}", repo.display()),
+            }
+        }
+
+        error!("Could not abort the merge in {}", repo.display());
+    }
+
+    /// Fetch all the remotes of the repository, pruning the refs deleted from them.
+    async fn fetch_all_remotes(&self, ctx: &ExecutionContext<'_>, repo: &Path) -> Result<()> {
+        let mut command = AsyncCommand::new(&self.git);
+        command
+            .stdin(Stdio::null())
+            .current_dir(repo)
+            .args(["fetch", "--all", "--prune"]);
+        if !ctx.config().git_allow_prompt() {
+            command.env("GIT_TERMINAL_PROMPT", "0");
+        }
+
+        let output = command.output().await?;
+        // Pruned refs are reported as ` - [deleted]         (none)     -> origin/branch`
+        for pruned in String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .filter(|line| line.contains("[deleted]"))
+        {
+            debug!("Pruned in {}: {}", repo.display(), pruned.trim());
+        }
+
+        output_checked_utf8(output)
+    }
+
+    /// Stash the uncommitted changes to the tracked files of the repository.
+    /// Returns whether there was anything to stash.
+    async fn stash_changes(&self, repo: &Path) -> Result<bool> {
+        let status_output = AsyncCommand::new(&self.git)
+            .stdin(Stdio::null())
+            .current_dir(repo)
+            .args(["status", "--porcelain", "--untracked-files=no"])
+            .output()
+            .await?;
+        if !status_output.status.success() || status_output.stdout.is_empty() {
+            return Ok(false);
+        }
+
+        let stash_output = AsyncCommand::new(&self.git)
+            .stdin(Stdio::null())
+            .current_dir(repo)
+            .args(["stash", "push", "--message", "topgrade auto-stash"])
+            .output()
+            .await?;
+        output_checked_utf8(stash_output)
+            .wrap_err_with(|| format!("Failed to stash the changes of {}", repo.display()))?;
+
+        Ok(true)
     }
 
     /// Pull the repositories specified in `self.repos`.
@@ -376,6 +723,12 @@ impl RepoStep {
             return Ok(());
         }
 
+        let mut lfs = ctx.config().git_lfs();
+        if lfs && which("git-lfs").is_none() {
+            print_warning(t!("git-lfs is not installed, not pulling the LFS files"));
+            lfs = false;
+        }
+
         if !ctx.config().verbose() {
             println!(
                 "\n{} {}\n",
@@ -399,7 +752,19 @@ impl RepoStep {
                 }
                 _ => true, // repo has remotes or command to check for remotes has failed. proceed to pull anyway.
             })
-            .map(|repo| self.pull_repo(ctx, repo));
+            .filter(|repo| match self.check_branch(repo) {
+                Some(reason) => {
+                    println!(
+                        "{} {} {}",
+                        style(t!("Skipping")).yellow().bold(),
+                        repo.display(),
+                        reason
+                    );
+                    false
+                }
+                None => true,
+            })
+            .map(|repo| async move { (repo, self.pull_repo(ctx, repo, lfs).await) });
 
         let stream_of_futures = if let Some(limit) = ctx.config().git_concurrency_limit() {
             iter(futures_iterator).buffer_unordered(limit).boxed()
@@ -408,9 +773,24 @@ impl RepoStep {
         };
 
         let basic_rt = runtime::Runtime::new()?;
-        let results = basic_rt.block_on(async { stream_of_futures.collect::<Vec<Result<()>>>().await });
+        let results = basic_rt.block_on(async { stream_of_futures.collect::<Vec<_>>().await });
+
+        let mut updated = Vec::new();
+        for (repo, result) in results {
+            match result? {
+                0 => (),
+                1 => updated.push(t!("{repo}: 1 new commit", repo = repo.display()).to_string()),
+                count => {
+                    updated.push(t!("{repo}: {count} new commits", repo = repo.display(), count = count).to_string())
+                }
+            }
+        }
 
-        let error = results.into_iter().find(|r| r.is_err());
-        error.unwrap_or(Ok(()))
+        updated.sort();
+        if updated.is_empty() {
+            Err(NoChanges.into())
+        } else {
+            Err(Updated(updated).into())
+        }
     }
 }
@@ -1,13 +1,70 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use color_eyre::eyre::Result;
+use rust_i18n::t;
 
 use crate::command::CommandExt;
+use crate::error::SkipStep;
 use crate::execution_context::ExecutionContext;
 use crate::terminal::print_separator;
 use crate::utils;
-use crate::utils::PathExt;
+use crate::utils::{dir_size, PathExt};
+use crate::HOME_DIR;
+
+/// Install the latest Go release with the `golang.org/dl` wrapper, when Go itself
+/// was installed that way. <https://go.dev/doc/manage-install>
+pub fn run_go_toolchain_update(ctx: &ExecutionContext) -> Result<()> {
+    if !ctx.config().go_update_toolchain() {
+        return Err(SkipStep(t!("Go toolchain updates are not enabled in the configuration").to_string()).into());
+    }
+
+    let go = utils::require("go")?;
+    let goroot = go_env(&go, "GOROOT")?;
+    // `golang.org/dl` downloads the toolchains to `~/sdk/goX.Y.Z`
+    if !Path::new(&goroot).starts_with(HOME_DIR.join("sdk")) {
+        return Err(SkipStep(t!("Go was not installed with golang.org/dl").to_string()).into());
+    }
+
+    let curl = utils::require("curl")?;
+    let current = go_env(&go, "GOVERSION")?;
+    // The first line is the version, e.g. `go1.22.3`
+    let latest = Command::new(curl)
+        .args(["-fsSL", "https://go.dev/VERSION?m=text"])
+        .output_checked_utf8()?
+        .stdout
+        .lines()
+        .next()
+        .map(|line| line.trim().to_string())
+        .unwrap_or_default();
+
+    print_separator(t!("Go toolchain"));
+
+    if latest.is_empty() || latest == current {
+        println!("{}", t!("Go is up to date ({version})", version = current));
+        return Ok(());
+    }
+
+    ctx.run_type()
+        .execute(&go)
+        .args(["install", &format!("golang.org/dl/{latest}@latest")])
+        .status_checked()?;
+    ctx.run_type()
+        .execute(gopath_bin(&go)?.join(&latest))
+        .arg("download")
+        .status_checked()?;
+
+    println!(
+        "{}",
+        t!(
+            "Go {version} was installed to {path}",
+            version = latest,
+            path = HOME_DIR.join("sdk").join(&latest).display()
+        )
+    );
+
+    Ok(())
+}
 
 /// <https://github.com/Gelio/go-global-update>
 pub fn run_go_global_update(ctx: &ExecutionContext) -> Result<()> {
@@ -27,19 +84,52 @@ pub fn run_go_gup(ctx: &ExecutionContext) -> Result<()> {
     ctx.run_type().execute(gup).arg("update").status_checked()
 }
 
+/// Remove the build and module caches with `go clean -cache -modcache`, when cleaning up.
+/// Opt-in with `go.clean_cache`, as the modules have to be downloaded again by the next builds.
+pub fn run_go_clean_cache(ctx: &ExecutionContext) -> Result<()> {
+    if !ctx.config().cleanup() || !ctx.config().go_clean_cache() {
+        return Err(SkipStep(t!("Go cache cleanup is not enabled in the configuration").to_string()).into());
+    }
+
+    let go = utils::require("go")?;
+    let caches = [go_env(&go, "GOCACHE")?, go_env(&go, "GOMODCACHE")?];
+    let size = || caches.iter().map(|cache| dir_size(Path::new(cache))).sum::<u64>();
+    let before = size();
+
+    print_separator(t!("Go cache"));
+
+    ctx.run_type()
+        .execute(&go)
+        .args(["clean", "-cache", "-modcache"])
+        .status_checked()?;
+
+    if !ctx.run_type().dry() {
+        utils::print_reclaimed(before.saturating_sub(size()));
+    }
+
+    Ok(())
+}
+
 /// Get the path of a Go binary.
 fn require_go_bin(name: &str) -> Result<PathBuf> {
     utils::require(name).or_else(|_| {
         let go = utils::require("go")?;
-        // TODO: Does this work? `go help gopath` says that:
-        // > The GOPATH environment variable lists places to look for Go code.
-        // > On Unix, the value is a colon-separated string.
-        // > On Windows, the value is a semicolon-separated string.
-        // > On Plan 9, the value is a list.
-        // Should we also fallback to the env variable?
-        let gopath_output = Command::new(go).args(["env", "GOPATH"]).output_checked_utf8()?;
-        let gopath = gopath_output.stdout.trim();
-
-        PathBuf::from(gopath).join("bin").join(name).require()
+        gopath_bin(&go)?.join(name).require()
     })
 }
+
+/// Get the `bin` directory of the `GOPATH`.
+fn gopath_bin(go: &Path) -> Result<PathBuf> {
+    // TODO: Does this work? `go help gopath` says that:
+    // > The GOPATH environment variable lists places to look for Go code.
+    // > On Unix, the value is a colon-separated string.
+    // > On Windows, the value is a semicolon-separated string.
+    // > On Plan 9, the value is a list.
+    // Should we also fallback to the env variable?
+    Ok(PathBuf::from(go_env(go, "GOPATH")?).join("bin"))
+}
+
+fn go_env(go: &Path, name: &str) -> Result<String> {
+    let output = Command::new(go).args(["env", name]).output_checked_utf8()?;
+    Ok(output.stdout.trim().to_string())
+}
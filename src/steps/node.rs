@@ -106,6 +106,16 @@ impl NPM {
         Ok(())
     }
 
+    /// Remove unreferenced packages from the cache (npm) or the store (pnpm).
+    /// Both tools print how much was reclaimed.
+    fn cleanup(&self, ctx: &ExecutionContext) -> Result<()> {
+        let args = match self.variant {
+            NPMVariant::Npm => ["cache", "verify"],
+            NPMVariant::Pnpm => ["store", "prune"],
+        };
+        ctx.run_type().execute(&self.command).args(args).status_checked()
+    }
+
     #[cfg(target_os = "linux")]
     pub fn should_use_sudo(&self) -> Result<bool> {
         let npm_root = self.root()?;
@@ -133,15 +143,26 @@ impl Yarn {
         }
     }
 
-    fn has_global_subcmd(&self) -> bool {
-        // Get the version of Yarn. After Yarn 2.x (berry),
-        // “yarn global” has been replaced with “yarn dlx”.
-        //
-        // As “yarn dlx” don't need to “upgrade”, we
-        // ignore the whole task if Yarn is 2.x or above.
-        let version = Command::new(&self.command).args(["--version"]).output_checked_utf8();
+    /// Whether this is Yarn Berry (2.x or above), or a Yarn whose version can't be found.
+    ///
+    /// Berry removed `yarn global` in favor of `yarn dlx`, so there are no
+    /// global packages to upgrade. `yarn up` only works inside a project.
+    fn is_berry(&self) -> bool {
+        let version = match Command::new(&self.command).args(["--version"]).output_checked_utf8() {
+            Ok(output) => output.stdout.trim().to_owned(),
+            Err(e) => {
+                debug!("Could not get the version of Yarn: {e}");
+                return true;
+            }
+        };
 
-        matches!(version, Ok(ver) if ver.stdout.starts_with('1') || ver.stdout.starts_with('0'))
+        match Version::parse(&version) {
+            Ok(version) => version.major >= 2,
+            Err(e) => {
+                debug!("Could not parse the version {version} of Yarn, checking its first digit: {e}");
+                !(version.starts_with('1') || version.starts_with('0'))
+            }
+        }
     }
 
     #[cfg(target_os = "linux")]
@@ -304,14 +325,16 @@ pub fn run_npm_upgrade(ctx: &ExecutionContext) -> Result<()> {
     print_separator(t!("Node Package Manager"));
 
     #[cfg(target_os = "linux")]
-    {
-        npm.upgrade(ctx, should_use_sudo(&npm, ctx)?)
-    }
+    npm.upgrade(ctx, should_use_sudo(&npm, ctx)?)?;
 
     #[cfg(not(target_os = "linux"))]
-    {
-        npm.upgrade(ctx, false)
+    npm.upgrade(ctx, false)?;
+
+    if ctx.config().cleanup() {
+        npm.cleanup(ctx)?;
     }
+
+    Ok(())
 }
 
 pub fn run_pnpm_upgrade(ctx: &ExecutionContext) -> Result<()> {
@@ -320,22 +343,23 @@ pub fn run_pnpm_upgrade(ctx: &ExecutionContext) -> Result<()> {
     print_separator(t!("Performant Node Package Manager"));
 
     #[cfg(target_os = "linux")]
-    {
-        pnpm.upgrade(ctx, should_use_sudo(&pnpm, ctx)?)
-    }
+    pnpm.upgrade(ctx, should_use_sudo(&pnpm, ctx)?)?;
 
     #[cfg(not(target_os = "linux"))]
-    {
-        pnpm.upgrade(ctx, false)
+    pnpm.upgrade(ctx, false)?;
+
+    if ctx.config().cleanup() {
+        pnpm.cleanup(ctx)?;
     }
+
+    Ok(())
 }
 
 pub fn run_yarn_upgrade(ctx: &ExecutionContext) -> Result<()> {
     let yarn = require("yarn").map(Yarn::new)?;
 
-    if !yarn.has_global_subcmd() {
-        debug!("Yarn is 2.x or above, skipping global upgrade");
-        return Ok(());
+    if yarn.is_berry() {
+        return Err(SkipStep(t!("Yarn Berry (2.x or above) has no global packages to upgrade").to_string()).into());
     }
 
     print_separator(t!("Yarn Package Manager"));
@@ -1,11 +1,12 @@
 use crate::command::CommandExt;
 use crate::execution_context::ExecutionContext;
-use crate::terminal::print_separator;
+use crate::terminal::{print_info, print_separator};
 use crate::utils::{get_require_sudo_string, require_option};
 use crate::Step;
 use color_eyre::eyre::Result;
 use rust_i18n::t;
 use std::process::Command;
+use tracing::debug;
 
 pub fn upgrade_freebsd(ctx: &ExecutionContext) -> Result<()> {
     let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
@@ -20,15 +21,43 @@ pub fn upgrade_packages(ctx: &ExecutionContext) -> Result<()> {
     let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
     print_separator(t!("FreeBSD Packages"));
 
+    let locked = locked_packages();
+    if !locked.is_empty() {
+        print_info(t!("Skipping locked packages: {packages}", packages = locked.join(" ")));
+    }
+
     let mut command = ctx.run_type().execute(sudo);
 
     command.args(["/usr/sbin/pkg", "upgrade"]);
     if ctx.config().yes(Step::System) {
         command.arg("-y");
     }
+    if let Some(args) = ctx.config().freebsd_pkg_args() {
+        command.args(args.split_whitespace());
+    }
     command.status_checked()
 }
 
+/// The packages locked with `pkg lock`, which `pkg upgrade` leaves alone.
+fn locked_packages() -> Vec<String> {
+    match Command::new("/usr/sbin/pkg")
+        .args(["lock", "-lq"])
+        .output_checked_utf8()
+    {
+        Ok(output) => output
+            .stdout
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(e) => {
+            debug!("Could not list the locked packages: {e}");
+            Vec::new()
+        }
+    }
+}
+
 pub fn audit_packages(ctx: &ExecutionContext) -> Result<()> {
     let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
 
@@ -1,22 +1,38 @@
+use std::ffi::OsString;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
 
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{Context, Result};
+use etcetera::base_strategy::BaseStrategy;
 use ini::Ini;
 use rust_i18n::t;
 use tracing::{debug, warn};
 
 use crate::command::CommandExt;
-use crate::error::{SkipStep, TopgradeError};
+use crate::config::{ArchPackageManager, Config};
+use crate::error::{PartialFailure, RebootRequired, SkipStep, TopgradeError, Updated};
 use crate::execution_context::ExecutionContext;
+use crate::executor::Executor;
 use crate::steps::generic::is_wsl;
 use crate::steps::os::archlinux;
-use crate::terminal::{print_separator, prompt_yesno};
+use crate::terminal::{print_info, print_separator, print_warning, prompt_yesno};
 use crate::utils::{get_require_sudo_string, require, require_option, which, PathExt};
-use crate::{Step, HOME_DIR};
+use crate::{Step, HOME_DIR, XDG_DIRS};
 
 static OS_RELEASE_PATH: &str = "/etc/os-release";
 
+/// The remote of the `flatpak.apps` entries that don't name one.
+const FLATPAK_DEFAULT_REMOTE: &str = "flathub";
+
+/// How long to wait before retrying a package manager whose lock is held.
+const LOCK_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// The pacman mirror list refreshed by reflector, and the copy of the previous one.
+const MIRRORLIST: &str = "/etc/pacman.d/mirrorlist";
+const MIRRORLIST_BACKUP: &str = "/etc/pacman.d/mirrorlist.bak";
+
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Distribution {
@@ -136,8 +152,24 @@ impl Distribution {
     }
 
     pub fn upgrade(self, ctx: &ExecutionContext) -> Result<()> {
+        if ctx.config().download_only() && !matches!(self, Distribution::Debian) && !self.redhat_based() {
+            return Err(SkipStep(t!("Download-only mode is not supported by this step").to_string()).into());
+        }
+
+        if ctx.config().security_only() && !matches!(self, Distribution::Debian) && !self.redhat_based() {
+            return Err(SkipStep(t!("Security-only mode is not supported by this step").to_string()).into());
+        }
+
         print_separator(t!("System update"));
 
+        if let Err(e) = self.refresh_mirrors(ctx) {
+            print_warning(t!(
+                "Could not refresh the mirrors, using the current ones: {error}",
+                error = e
+            ));
+        }
+
+        let kernels = self.installed_kernels();
         match self {
             Distribution::Alpine => upgrade_alpine_linux(ctx),
             Distribution::Chimera => upgrade_chimera_linux(ctx),
@@ -162,12 +194,113 @@ impl Distribution {
             Distribution::PCLinuxOS => upgrade_pclinuxos(ctx),
             Distribution::Nobara => upgrade_nobara(ctx),
             Distribution::NILRT => upgrade_nilrt(ctx),
+        }?;
+
+        if !ctx.run_type().dry()
+            && !ctx.config().download_only()
+            && self.installed_kernels().iter().any(|kernel| !kernels.contains(kernel))
+        {
+            print_warning(t!("A new kernel was installed, a reboot is recommended"));
+            return Err(RebootRequired.into());
         }
+
+        Ok(())
     }
 
-    pub fn show_summary(self) {
-        if let Distribution::Arch = self {
-            archlinux::show_pacnew();
+    /// Refresh the mirror list for `linux.country`, when a supported tool is installed.
+    fn refresh_mirrors(self, ctx: &ExecutionContext) -> Result<()> {
+        let Some(country) = ctx.config().linux_country() else {
+            return Ok(());
+        };
+
+        match self {
+            Distribution::Arch => {
+                let Some(reflector) = which("reflector") else {
+                    debug!("reflector isn't installed, not refreshing the mirrors");
+                    return Ok(());
+                };
+                let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
+                // reflector overwrites the list, keep the previous one to go back to
+                ctx.run_type()
+                    .execute(sudo)
+                    .args(["cp", "--preserve=all", MIRRORLIST, MIRRORLIST_BACKUP])
+                    .status_checked()?;
+                ctx.run_type()
+                    .execute(sudo)
+                    .arg(reflector)
+                    .args(["--country", country, "--latest", "20", "--sort", "rate"])
+                    .args(["--save", MIRRORLIST])
+                    .status_checked()
+            }
+            Distribution::Debian => {
+                // Debian's sources may be split across files, so the list is only suggested
+                let dir = XDG_DIRS.cache_dir().join("topgrade");
+                let sources = dir.join("sources.list");
+                if let Some(apt_select) = which("apt-select") {
+                    fs::create_dir_all(&dir)?;
+                    ctx.run_type()
+                        .execute(apt_select)
+                        .current_dir(&dir)
+                        .args(["--country", country])
+                        .status_checked()?;
+                } else if let Some(netselect_apt) = which("netselect-apt") {
+                    fs::create_dir_all(&dir)?;
+                    let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
+                    ctx.run_type()
+                        .execute(sudo)
+                        .arg(netselect_apt)
+                        .args(["-c", country, "-o"])
+                        .arg(&sources)
+                        .status_checked()?;
+                } else {
+                    debug!("Neither apt-select nor netselect-apt is installed, not looking for mirrors");
+                    return Ok(());
+                }
+
+                println!(
+                    "{}",
+                    t!(
+                        "The fastest mirrors of {country} were written to {path}, review it before using it in /etc/apt",
+                        country = country,
+                        path = sources.display()
+                    )
+                );
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Installed kernel packages with their versions, to notice kernel updates.
+    /// Only supported on Debian and Red Hat-based distributions.
+    fn installed_kernels(self) -> Vec<String> {
+        let output = match self {
+            Distribution::Debian => Command::new("dpkg-query")
+                .args([
+                    "-W",
+                    "-f",
+                    "${db:Status-Abbrev} ${Package} ${Version}\\n",
+                    "linux-image-*",
+                ])
+                .output_checked_utf8(),
+            _ if self.redhat_based() => Command::new("rpm").args(["-q", "kernel"]).output_checked_utf8(),
+            _ => return Vec::new(),
+        };
+
+        match output {
+            Ok(output) => output
+                .stdout
+                .lines()
+                .filter_map(|line| match self {
+                    Distribution::Debian => line.strip_prefix("ii "),
+                    _ => Some(line),
+                })
+                .map(|line| line.trim().to_string())
+                .collect(),
+            Err(e) => {
+                debug!("Could not list the installed kernels: {e}");
+                Vec::new()
+            }
         }
     }
 
@@ -205,7 +338,30 @@ fn upgrade_alpine_linux(ctx: &ExecutionContext) -> Result<()> {
     let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
 
     ctx.run_type().execute(sudo).arg(&apk).arg("update").status_checked()?;
-    ctx.run_type().execute(sudo).arg(&apk).arg("upgrade").status_checked()
+    let Err(e) = ctx.run_type().execute(sudo).arg(&apk).arg("upgrade").status_checked() else {
+        return Ok(());
+    };
+
+    if !ctx.config().apk_autofix() {
+        return Err(e);
+    }
+
+    print_warning(t!("apk upgrade failed, trying to repair with apk fix"));
+    let retry = ctx
+        .run_type()
+        .execute(sudo)
+        .arg(&apk)
+        .arg("fix")
+        .status_checked()
+        .and_then(|()| ctx.run_type().execute(sudo).arg(&apk).arg("upgrade").status_checked());
+
+    // The original failure is more useful than the one of the repair attempt
+    if let Err(retry_error) = retry {
+        debug!("apk fix did not help: {retry_error}");
+        return Err(e);
+    }
+
+    Ok(())
 }
 
 fn upgrade_chimera_linux(ctx: &ExecutionContext) -> Result<()> {
@@ -225,8 +381,17 @@ fn upgrade_wolfi_linux(ctx: &ExecutionContext) -> Result<()> {
 }
 
 fn upgrade_redhat(ctx: &ExecutionContext) -> Result<()> {
+    let download_only = ctx.config().download_only();
+    let security_only = ctx.config().security_only();
+
     if let Some(bootc) = which("bootc") {
         if ctx.config().bootc() {
+            if download_only {
+                return Err(SkipStep(t!("Download-only mode is not supported by this step").to_string()).into());
+            }
+            if security_only {
+                return Err(SkipStep(t!("Security-only mode is not supported by this step").to_string()).into());
+            }
             let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
             return ctx.run_type().execute(sudo).arg(&bootc).arg("upgrade").status_checked();
         }
@@ -234,31 +399,99 @@ fn upgrade_redhat(ctx: &ExecutionContext) -> Result<()> {
 
     if let Some(ostree) = which("rpm-ostree") {
         if ctx.config().rpm_ostree() {
+            if security_only {
+                return Err(SkipStep(t!("Security-only mode is not supported by this step").to_string()).into());
+            }
             let mut command = ctx.run_type().execute(ostree);
             command.arg("upgrade");
+            if download_only {
+                command.arg("--download-only");
+            }
             return command.status_checked();
         }
     };
 
     let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
-    let mut command = ctx.run_type().execute(sudo);
-    command
-        .arg(which("dnf").unwrap_or_else(|| Path::new("yum").to_path_buf()))
-        .arg(if ctx.config().redhat_distro_sync() {
-            "distro-sync"
-        } else {
-            "upgrade"
-        });
+    let dnf = which("dnf").unwrap_or_else(|| Path::new("yum").to_path_buf());
 
-    if let Some(args) = ctx.config().dnf_arguments() {
-        command.args(args.split_whitespace());
+    if ctx.config().show_changelogs() && !ctx.config().yes(Step::System) {
+        if let Err(e) = Command::new(&dnf).args(["updateinfo", "info"]).status_checked() {
+            debug!("Could not show the advisories of the updates: {e}");
+        }
     }
+    let update_repos = ctx.config().show_update_repos().then(|| dnf_update_repos(&dnf));
 
-    if ctx.config().yes(Step::System) {
-        command.arg("-y");
+    retry_on_lock(ctx, || {
+        let mut command = ctx.run_type().execute(sudo);
+        command
+            .arg(&dnf)
+            .arg(if ctx.config().redhat_distro_sync() && !security_only {
+                "distro-sync"
+            } else {
+                "upgrade"
+            });
+
+        if let Some(args) = ctx.config().dnf_arguments() {
+            command.args(args.split_whitespace());
+        }
+
+        if security_only {
+            command.arg("--security");
+        }
+
+        if ctx.config().dnf_allowerasing() {
+            command.arg("--allowerasing");
+        }
+
+        match ctx.config().dnf_best() {
+            Some(true) => {
+                command.arg("--best");
+            }
+            Some(false) => {
+                command.arg("--nobest");
+            }
+            None => (),
+        }
+
+        // sudo usually doesn't keep the proxy environment variables
+        if let Some(proxy) = ctx.config().proxy() {
+            command.arg(format!("--setopt=proxy={proxy}"));
+        }
+
+        if download_only {
+            command.arg("--downloadonly");
+        }
+
+        if ctx.config().yes(Step::System) {
+            command.arg("-y");
+        }
+
+        command.status_checked_with_stderr()
+    })?;
+
+    if let Some(repos) = update_repos {
+        report_update_repos(repos);
+    }
+
+    if ctx.config().cleanup() && !download_only {
+        let orphans = dnf_autoremovable_packages(&dnf);
+        if ctx.config().autoremove_dry_run() {
+            report_autoremovable_packages(&orphans);
+        } else {
+            retry_on_lock(ctx, || {
+                let mut command = ctx.run_type().execute(sudo);
+                command.arg(&dnf).arg("autoremove");
+                if ctx.config().yes(Step::System) {
+                    command.arg("-y");
+                }
+                command.status_checked_with_stderr()
+            })?;
+            if !ctx.run_type().dry() {
+                report_removed_packages(&orphans);
+            }
+        }
     }
 
-    command.status_checked()?;
     Ok(())
 }
 
@@ -532,6 +765,15 @@ fn upgrade_debian(ctx: &ExecutionContext) -> Result<()> {
 
     let is_mist = apt.ends_with("mist");
     let is_nala = apt.ends_with("nala");
+    let download_only = ctx.config().download_only();
+
+    if download_only && (is_mist || is_nala) {
+        return Err(SkipStep(t!("Download-only mode is not supported by this step").to_string()).into());
+    }
+
+    if ctx.config().security_only() {
+        return upgrade_debian_security(ctx, download_only);
+    }
 
     // MIST does not require `sudo`
     if is_mist {
@@ -544,41 +786,482 @@ fn upgrade_debian(ctx: &ExecutionContext) -> Result<()> {
     }
 
     let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
+
+    if ctx.config().apt_unhold() && !download_only {
+        let held = apt_held_packages()?;
+        if !held.is_empty() {
+            print_warning(t!("Unholding packages: {packages}", packages = held.join(" ")));
+            ctx.run_type()
+                .execute(sudo)
+                .args(["apt-mark", "unhold"])
+                .args(&held)
+                .status_checked()?;
+        }
+    }
+
+    let acquire_args: Vec<String> = apt_acquire_options(ctx)
+        .into_iter()
+        .flat_map(|(option, value)| [String::from("-o"), format!("{option}={value}")])
+        .collect();
+
     if !is_nala {
+        apt_update(ctx, sudo, &apt, &acquire_args)?;
+    }
+
+    if ctx.config().show_changelogs() && !ctx.config().yes(Step::System) {
+        show_apt_changelogs();
+    }
+
+    let update_repos = ctx.config().show_update_repos().then(apt_update_repos);
+
+    let upgrade = || {
+        retry_on_lock(ctx, || {
+            let mut command = ctx.run_type().execute(sudo);
+            command.arg(&apt);
+            if is_nala {
+                command.arg("upgrade");
+            } else {
+                command.arg("dist-upgrade");
+            };
+            if ctx.config().yes(Step::System) {
+                command.arg("-y");
+            }
+            if download_only {
+                command.arg("--download-only");
+            }
+            if let Some(args) = ctx.config().apt_arguments() {
+                command.args(args.split_whitespace());
+            }
+            command.args(&acquire_args);
+            command.status_checked_with_stderr()
+        })
+    };
+
+    if let Err(e) = upgrade() {
+        if !ctx.config().dpkg_auto_configure() || !dpkg_interrupted(&e) {
+            return Err(e);
+        }
+
+        print_warning(t!("dpkg was interrupted, running dpkg --configure -a"));
         ctx.run_type()
             .execute(sudo)
-            .arg(&apt)
-            .arg("update")
-            .status_checked_with_codes(&[0, 100])?;
+            .args(["dpkg", "--configure", "-a"])
+            .status_checked()
+            .and_then(|()| upgrade())
+            .map_err(|second| {
+                let message = t!(
+                    "Upgrading failed again after dpkg --configure -a, first attempt: {first}, second attempt: {second}",
+                    first = format!("{e:#}"),
+                    second = format!("{second:#}")
+                );
+                second.wrap_err(message)
+            })?;
     }
 
-    let mut command = ctx.run_type().execute(sudo);
-    command.arg(&apt);
-    if is_nala {
-        command.arg("upgrade");
+    if let Some(repos) = update_repos {
+        report_update_repos(repos);
+    }
+
+    // `apt-get clean` would delete the packages we just downloaded
+    if ctx.config().cleanup() && !download_only {
+        ctx.run_type().execute(sudo).arg(&apt).arg("clean").status_checked()?;
+
+        let orphans = apt_autoremovable_packages();
+        if ctx.config().autoremove_dry_run() {
+            report_autoremovable_packages(&orphans);
+        } else {
+            retry_on_lock(ctx, || {
+                let mut command = ctx.run_type().execute(sudo);
+                command.arg(&apt).arg("autoremove");
+                if ctx.config().apt_autoremove_purge() {
+                    command.arg("--purge");
+                }
+                if ctx.config().yes(Step::System) {
+                    command.arg("-y");
+                }
+                command.status_checked_with_stderr()
+            })?;
+            if !ctx.run_type().dry() {
+                report_removed_packages(&orphans);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply only security updates through `unattended-upgrade`, which upgrades
+/// packages from the origins allowed in its configuration (security by default).
+fn upgrade_debian_security(ctx: &ExecutionContext, download_only: bool) -> Result<()> {
+    let unattended_upgrade = require_option(
+        which("unattended-upgrade"),
+        t!("Security-only mode requires unattended-upgrade to be installed").to_string(),
+    )?;
+    let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
+
+    let options = apt_acquire_options(ctx);
+    let acquire_args: Vec<String> = options
+        .iter()
+        .flat_map(|(option, value)| [String::from("-o"), format!("{option}={value}")])
+        .collect();
+    apt_update(ctx, sudo, Path::new("apt-get"), &acquire_args)?;
+
+    // unattended-upgrade has no `-o`, the options go through a configuration file
+    let apt_config = if options.is_empty() {
+        None
     } else {
-        command.arg("dist-upgrade");
+        Some(apt_config_with(ctx, &options)?)
     };
-    if ctx.config().yes(Step::System) {
-        command.arg("-y");
+
+    retry_on_lock(ctx, || {
+        let mut command = ctx.run_type().execute(sudo);
+        if let Some(apt_config) = &apt_config {
+            command.arg("env").arg(format!("APT_CONFIG={}", apt_config.display()));
+        }
+        command.arg(&unattended_upgrade).arg("-v");
+        if download_only {
+            command.arg("--download-only");
+        }
+        command.status_checked_with_stderr()
+    })
+}
+
+/// The apt options for `misc.proxy` and `misc.bandwidth_limit`, which have to be
+/// given explicitly since sudo usually doesn't keep the proxy environment variables.
+fn apt_acquire_options(ctx: &ExecutionContext) -> Vec<(&'static str, String)> {
+    let mut options = Vec::new();
+    if let Some(proxy) = ctx.config().proxy() {
+        options.push(("Acquire::http::Proxy", proxy.to_string()));
+        options.push(("Acquire::https::Proxy", proxy.to_string()));
     }
-    if let Some(args) = ctx.config().apt_arguments() {
-        command.args(args.split_whitespace());
+    if let Some(limit) = ctx.config().bandwidth_limit() {
+        options.push(("Acquire::http::Dl-Limit", limit.to_string()));
     }
-    command.status_checked()?;
+    options
+}
 
-    if ctx.config().cleanup() {
-        ctx.run_type().execute(sudo).arg(&apt).arg("clean").status_checked()?;
+/// Run `apt update`, retrying while another process holds the lock.
+fn apt_update(ctx: &ExecutionContext, sudo: &crate::sudo::Sudo, apt: &Path, acquire_args: &[String]) -> Result<()> {
+    retry_on_lock(ctx, || {
+        let result = ctx
+            .run_type()
+            .execute(sudo)
+            .arg(apt)
+            .arg("update")
+            .args(acquire_args)
+            .status_checked_with_stderr();
+        // apt-get update exits with 100 when some of the repositories couldn't be fetched
+        match result {
+            Err(e) if !is_lock_contention(&e) && exit_code(&e) == Some(100) => Ok(()),
+            result => result,
+        }
+    })
+}
 
-        let mut command = ctx.run_type().execute(sudo);
-        command.arg(&apt).arg("autoremove");
-        if ctx.config().yes(Step::System) {
-            command.arg("-y");
+/// Write an apt configuration file for `APT_CONFIG` with `options`, after the contents
+/// of `/etc/apt/apt.conf` that it replaces.
+fn apt_config_with(ctx: &ExecutionContext, options: &[(&str, String)]) -> Result<PathBuf> {
+    let path = XDG_DIRS.cache_dir().join("topgrade").join("apt.conf");
+    if ctx.run_type().dry() {
+        return Ok(path);
+    }
+
+    let mut contents = fs::read_to_string("/etc/apt/apt.conf").unwrap_or_default();
+    for (option, value) in options {
+        contents.push_str(&format!("\n{option} \"{value}\";"));
+    }
+    contents.push('\n');
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, contents).wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Whether apt failed because dpkg was interrupted, in which case it asks to run
+/// `dpkg --configure -a`.
+fn dpkg_interrupted(e: &color_eyre::eyre::Error) -> bool {
+    matches!(
+        e.downcast_ref::<TopgradeError>(),
+        Some(TopgradeError::ProcessFailedWithOutput(_, _, stderr)) if stderr.contains("dpkg --configure -a")
+    )
+}
+
+/// Packages that `apt autoremove` would remove, from a simulated run.
+fn apt_autoremovable_packages() -> Vec<String> {
+    match Command::new("apt-get").args(["-s", "autoremove"]).output_checked_utf8() {
+        // Removals are simulated as `Remv <package> [<version>]`
+        Ok(output) => output
+            .stdout
+            .lines()
+            .filter_map(|line| line.strip_prefix("Remv "))
+            .filter_map(|line| line.split_whitespace().next())
+            .map(String::from)
+            .collect(),
+        Err(e) => {
+            debug!("Could not list the packages to autoremove: {e}");
+            Vec::new()
         }
-        command.status_checked()?;
     }
+}
 
-    Ok(())
+/// Packages that `apt-get dist-upgrade` would upgrade or install.
+fn apt_upgradable_packages() -> Vec<String> {
+    match Command::new("apt-get")
+        .args(["-s", "dist-upgrade"])
+        .output_checked_utf8()
+    {
+        // Installations are simulated as `Inst <package> [<old version>] (<new version> ...)`
+        Ok(output) => output
+            .stdout
+            .lines()
+            .filter_map(|line| line.strip_prefix("Inst "))
+            .filter_map(|line| line.split_whitespace().next())
+            .map(String::from)
+            .collect(),
+        Err(e) => {
+            debug!("Could not list the packages to upgrade: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// The repository of each package to upgrade.
+fn apt_update_repos() -> Vec<String> {
+    match Command::new("apt-get")
+        .args(["-s", "dist-upgrade"])
+        .output_checked_utf8()
+    {
+        // e.g. `Inst libfoo [1.0] (1.1 Ubuntu:22.04/jammy-updates [amd64])`, with the
+        // origins separated by `, ` when the version is in several repositories
+        Ok(output) => output
+            .stdout
+            .lines()
+            .filter(|line| line.starts_with("Inst "))
+            .filter_map(|line| line.split_once('(')?.1.split_whitespace().nth(1))
+            .map(|origin| origin.trim_end_matches(',').to_string())
+            .collect(),
+        Err(e) => {
+            debug!("Could not list the repositories of the updates: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Print the latest changelog entry of each package to upgrade.
+fn show_apt_changelogs() {
+    if which("apt-listchanges").is_some() {
+        debug!("apt-listchanges will show the changes during the upgrade");
+        return;
+    }
+
+    for package in apt_upgradable_packages() {
+        match Command::new("apt-get")
+            .args(["changelog", &package])
+            .output_checked_utf8()
+        {
+            // Entries end with the ` -- Maintainer <email>  Date` line
+            Ok(output) => {
+                for line in output.stdout.lines() {
+                    println!("{line}");
+                    if line.starts_with(" -- ") {
+                        break;
+                    }
+                }
+                println!();
+            }
+            Err(e) => debug!("Could not get the changelog of {package}: {e}"),
+        }
+    }
+}
+
+/// Show the notes of the distribution at the end of the summary, `None` if it wasn't detected.
+pub fn show_summary(distribution: Option<Distribution>, config: &Config) {
+    match distribution {
+        Some(Distribution::Arch) => archlinux::show_pacnew(),
+        Some(Distribution::Debian) => show_held_packages(),
+        _ if is_arch_like(config) => archlinux::show_pacnew(),
+        _ => (),
+    }
+}
+
+/// Whether the system uses pacman without being detected as Arch Linux, and the
+/// `.pacnew` files should be looked for anyway: with `linux.pacnew_scan`, or when
+/// an Arch-only helper like garuda-update or pamac is configured.
+fn is_arch_like(config: &Config) -> bool {
+    let arch_helper = matches!(
+        config.arch_package_manager(),
+        ArchPackageManager::GarudaUpdate | ArchPackageManager::Pamac
+    );
+    (config.pacnew_scan() || arch_helper) && which("pacman").is_some()
+}
+
+/// Run a package manager command, and retry it up to `linux.lock_retry` times while
+/// another process, like a background auto-updater, holds the package database lock.
+fn retry_on_lock(ctx: &ExecutionContext, run: impl Fn() -> Result<()>) -> Result<()> {
+    let retries = ctx.config().lock_retry();
+    let mut attempt = 0;
+    loop {
+        let result = run();
+        if !result.as_ref().is_err_and(is_lock_contention) {
+            return result;
+        }
+        if attempt == retries {
+            return result.wrap_err(t!("The package database lock is still held by another process"));
+        }
+
+        attempt += 1;
+        print_warning(t!(
+            "The package database is locked by another process, retrying in {seconds}s ({attempt}/{retries})",
+            seconds = LOCK_RETRY_DELAY.as_secs(),
+            attempt = attempt,
+            retries = retries
+        ));
+        std::thread::sleep(LOCK_RETRY_DELAY);
+    }
+}
+
+/// Whether a package manager failed because another process holds its lock.
+fn is_lock_contention(e: &color_eyre::eyre::Error) -> bool {
+    const PATTERNS: [&str; 4] = [
+        // apt
+        "could not get lock",
+        // unattended-upgrade
+        "lock could not be acquired",
+        // yum
+        "another app is currently holding",
+        // dnf5
+        "failed to obtain rpm transaction lock",
+    ];
+
+    matches!(
+        e.downcast_ref::<TopgradeError>(),
+        Some(TopgradeError::ProcessFailedWithOutput(_, _, stderr))
+            if PATTERNS.iter().any(|pattern| stderr.to_lowercase().contains(pattern))
+    )
+}
+
+/// The exit code of a command that failed with `status_checked_with_stderr`.
+fn exit_code(e: &color_eyre::eyre::Error) -> Option<i32> {
+    match e.downcast_ref::<TopgradeError>() {
+        Some(TopgradeError::ProcessFailedWithOutput(_, status, _)) => status.code(),
+        _ => None,
+    }
+}
+
+/// The repository of each package to upgrade.
+fn dnf_update_repos(dnf: &Path) -> Vec<String> {
+    match Command::new(dnf)
+        .args(["list", "--upgrades", "--quiet"])
+        .output_checked_utf8()
+    {
+        // `<name>.<arch> <version> <repository>`, after a heading
+        Ok(output) => output
+            .stdout
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .filter(|fields| fields.len() == 3)
+            .map(|fields| fields[2].to_string())
+            .collect(),
+        Err(e) => {
+            debug!("Could not list the repositories of the updates: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Print how many updates came from each repository, the largest first.
+fn report_update_repos(repos: Vec<String>) {
+    if repos.is_empty() {
+        return;
+    }
+
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for repo in repos {
+        match counts.iter_mut().find(|(r, _)| *r == repo) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((repo, 1)),
+        }
+    }
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+
+    println!("{}", t!("Updates by repository:"));
+    for (repo, count) in counts {
+        println!("    {repo}: {count}");
+    }
+}
+
+/// Packages that `dnf autoremove` would remove.
+fn dnf_autoremovable_packages(dnf: &Path) -> Vec<String> {
+    match Command::new(dnf)
+        .args(["repoquery", "--unneeded", "--quiet"])
+        .output_checked_utf8()
+    {
+        Ok(output) => output
+            .stdout
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(e) => {
+            debug!("Could not list the packages to autoremove: {e}");
+            Vec::new()
+        }
+    }
+}
+
+fn report_autoremovable_packages(packages: &[String]) {
+    if packages.is_empty() {
+        println!("{}", t!("No packages to autoremove"));
+    } else {
+        println!(
+            "{}",
+            t!(
+                "{count} packages would be removed by autoremove: {packages}",
+                count = packages.len(),
+                packages = packages.join(" ")
+            )
+        );
+    }
+}
+
+fn report_removed_packages(packages: &[String]) {
+    if !packages.is_empty() {
+        println!(
+            "{}",
+            t!(
+                "Removed {count} packages: {packages}",
+                count = packages.len(),
+                packages = packages.join(" ")
+            )
+        );
+    }
+}
+
+/// Packages marked with `apt-mark hold`, which apt won't upgrade.
+fn apt_held_packages() -> Result<Vec<String>> {
+    let output = Command::new("apt-mark").arg("showhold").output_checked_utf8()?;
+    Ok(output
+        .stdout
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+fn show_held_packages() {
+    match apt_held_packages() {
+        Ok(held) if !held.is_empty() => {
+            println!("\n{}", t!("Held packages that were not upgraded:"));
+            for package in held {
+                println!("{package}");
+            }
+        }
+        Ok(_) => (),
+        Err(e) => debug!("Failed to list held packages: {e}"),
+    }
 }
 
 pub fn run_deb_get(ctx: &ExecutionContext) -> Result<()> {
@@ -759,8 +1442,15 @@ fn upgrade_nixos(ctx: &ExecutionContext) -> Result<()> {
     if ctx.config().cleanup() {
         ctx.run_type()
             .execute(sudo)
-            .args(["/run/current-system/sw/bin/nix-collect-garbage", "-d"])
+            .arg("/run/current-system/sw/bin/nix-collect-garbage")
+            .args(super::unix::nix_collect_garbage_args(ctx))
             .status_checked()?;
+
+        if ctx.config().nix_optimise() {
+            let mut command = ctx.run_type().execute(sudo);
+            command.arg("/run/current-system/sw/bin/nix");
+            super::unix::optimise_nix_store(command)?;
+        }
     }
 
     Ok(())
@@ -878,66 +1568,427 @@ pub fn run_fwupdmgr(ctx: &ExecutionContext) -> Result<()> {
 
 pub fn run_flatpak(ctx: &ExecutionContext) -> Result<()> {
     let flatpak = require("flatpak")?;
-    let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
+    let scope = ctx.config().flatpak_scope();
     let cleanup = ctx.config().cleanup();
-    let yes = ctx.config().yes(Step::Flatpak);
-    let run_type = ctx.run_type();
-    print_separator("Flatpak User Packages");
+    let yes = ctx.config().flatpak_assume_yes() || ctx.config().yes(Step::Flatpak);
+    // Without `-y`, flatpak lists the runtimes to remove and asks once
+    let remove_yes = yes && !ctx.config().flatpak_confirm_removals();
+    let mut removed = Vec::new();
+    let mut failed = Vec::new();
+
+    if scope.includes_user() {
+        print_separator("Flatpak User Packages");
+
+        let masked = flatpak_masked_refs(&flatpak, "--user");
+        allow_masked_failure(
+            flatpak_update(ctx, &flatpak, None, "--user", yes),
+            &flatpak,
+            "--user",
+            &masked,
+        )?;
+
+        // The apps go to the system installation, unless only the user one is updated
+        if !scope.includes_system() {
+            failed.extend(flatpak_install_apps(ctx, &flatpak, None, "--user", yes)?);
+        }
 
-    let mut update_args = vec!["update", "--user"];
-    if yes {
-        update_args.push("-y");
+        if cleanup {
+            removed.extend(flatpak_remove_unused(ctx, &flatpak, None, "--user", remove_yes)?);
+        }
     }
-    run_type.execute(&flatpak).args(&update_args).status_checked()?;
 
-    if cleanup {
-        let mut cleanup_args = vec!["uninstall", "--user", "--unused"];
-        if yes {
-            cleanup_args.push("-y");
+    if scope.includes_system() {
+        print_separator(t!("Flatpak System Packages"));
+        let masked = flatpak_masked_refs(&flatpak, "--system");
+        let sudo = if ctx.config().flatpak_use_sudo() || std::env::var("SSH_CLIENT").is_ok() {
+            Some(require_option(ctx.sudo().as_ref(), get_require_sudo_string())?)
+        } else {
+            None
+        };
+        allow_masked_failure(
+            flatpak_update(ctx, &flatpak, sudo, "--system", yes),
+            &flatpak,
+            "--system",
+            &masked,
+        )?;
+        failed.extend(flatpak_install_apps(ctx, &flatpak, sudo, "--system", yes)?);
+        if cleanup {
+            removed.extend(flatpak_remove_unused(ctx, &flatpak, sudo, "--system", remove_yes)?);
+        }
+    }
+
+    if !failed.is_empty() {
+        Err(PartialFailure(failed).into())
+    } else if removed.is_empty() {
+        Ok(())
+    } else {
+        Err(Updated(vec![t!(
+            "Removed {count} unused runtimes: {runtimes}",
+            count = removed.len(),
+            runtimes = removed.join(", ")
+        )
+        .to_string()])
+        .into())
+    }
+}
+
+/// Install the apps of `flatpak.apps` that are missing in an installation, and update the
+/// others, with one `flatpak install --or-update` per remote. Returns the errors of the
+/// apps whose remote isn't configured in the installation, or of all of them when the
+/// remotes can't be listed.
+fn flatpak_install_apps(
+    ctx: &ExecutionContext,
+    flatpak: &Path,
+    sudo: Option<&crate::sudo::Sudo>,
+    installation: &str,
+    yes: bool,
+) -> Result<Vec<String>> {
+    let apps = ctx.config().flatpak_apps();
+    if apps.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let remotes = match Command::new(flatpak)
+        .args(["remotes", installation, "--columns=name"])
+        .output_checked_utf8()
+    {
+        Ok(output) => output.stdout,
+        Err(e) => {
+            return Ok(apps
+                .iter()
+                .map(|app| {
+                    let id = app.split_once(':').map_or(app.as_str(), |(_, id)| id);
+                    t!("{app}: could not list the remotes: {error}", app = id, error = e).to_string()
+                })
+                .collect());
+        }
+    };
+    let remotes: Vec<&str> = remotes.lines().map(str::trim).collect();
+
+    let mut by_remote: Vec<(&str, Vec<&str>)> = Vec::new();
+    let mut failed = Vec::new();
+    for app in apps {
+        let (remote, id) = app.split_once(':').unwrap_or((FLATPAK_DEFAULT_REMOTE, app));
+        if !remotes.contains(&remote) {
+            failed.push(
+                t!(
+                    "{app}: the remote {remote} is not configured",
+                    app = id,
+                    remote = remote
+                )
+                .to_string(),
+            );
+            continue;
+        }
+        match by_remote.iter_mut().find(|(r, _)| *r == remote) {
+            Some((_, ids)) => ids.push(id),
+            None => by_remote.push((remote, vec![id])),
         }
-        run_type.execute(&flatpak).args(&cleanup_args).status_checked()?;
     }
 
-    print_separator(t!("Flatpak System Packages"));
-    if ctx.config().flatpak_use_sudo() || std::env::var("SSH_CLIENT").is_ok() {
-        let mut update_args = vec!["update", "--system"];
+    for (remote, ids) in by_remote {
+        let mut command = flatpak_command(ctx, flatpak, sudo);
+        command.args(["install", "--or-update", installation]);
         if yes {
-            update_args.push("-y");
+            command.arg("-y");
         }
-        run_type
-            .execute(sudo)
-            .arg(&flatpak)
-            .args(&update_args)
+        command.arg(remote).args(ids).status_checked()?;
+    }
+
+    Ok(failed)
+}
+
+/// A `flatpak` command, run with `sudo` if given.
+fn flatpak_command(ctx: &ExecutionContext, flatpak: &Path, sudo: Option<&crate::sudo::Sudo>) -> Executor {
+    match sudo {
+        Some(sudo) => {
+            let mut command = ctx.run_type().execute(sudo);
+            command.arg(flatpak);
+            command
+        }
+        None => ctx.run_type().execute(flatpak),
+    }
+}
+
+/// Run `flatpak uninstall --unused` for an installation (`--user` or `--system`),
+/// with `flatpak.keep_runtimes` pinned so that they are kept. Returns the removed refs.
+///
+/// The runtimes that weren't pinned already are only pinned during the uninstall.
+fn flatpak_remove_unused(
+    ctx: &ExecutionContext,
+    flatpak: &Path,
+    sudo: Option<&crate::sudo::Sudo>,
+    installation: &str,
+    yes: bool,
+) -> Result<Vec<String>> {
+    let command = || flatpak_command(ctx, flatpak, sudo);
+
+    let pinned = flatpak_pinned(flatpak, installation);
+    let pins: Vec<&String> = ctx
+        .config()
+        .flatpak_keep_runtimes()
+        .iter()
+        .filter(|runtime| !pinned.contains(runtime))
+        .collect();
+    for runtime in &pins {
+        command()
+            .args(["pin", installation, runtime.as_str()])
             .status_checked()?;
-        if cleanup {
-            let mut cleanup_args = vec!["uninstall", "--system", "--unused"];
-            if yes {
-                cleanup_args.push("-y");
-            }
-            run_type
-                .execute(sudo)
-                .arg(flatpak)
-                .args(&cleanup_args)
-                .status_checked()?;
+    }
+
+    let before = flatpak_installed_runtimes(flatpak, installation);
+    let mut uninstall = command();
+    uninstall.args(["uninstall", installation, "--unused"]);
+    if yes {
+        uninstall.arg("-y");
+    }
+    let result = uninstall.status_checked();
+
+    for runtime in &pins {
+        command()
+            .args(["pin", "--remove", installation, runtime.as_str()])
+            .status_checked()?;
+    }
+    result?;
+
+    let after = flatpak_installed_runtimes(flatpak, installation);
+    Ok(removed_runtimes(before, &after))
+}
+
+/// The runtimes of `before` that are not installed anymore.
+fn removed_runtimes(before: Vec<String>, after: &[String]) -> Vec<String> {
+    before.into_iter().filter(|runtime| !after.contains(runtime)).collect()
+}
+
+/// The patterns pinned with `flatpak pin` in the given installation.
+fn flatpak_pinned(flatpak: &Path, installation: &str) -> Vec<String> {
+    match Command::new(flatpak).args(["pin", installation]).output_checked_utf8() {
+        // One pattern per line, or a message when there are none
+        Ok(output) => output
+            .stdout
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.contains('/'))
+            .map(String::from)
+            .collect(),
+        Err(e) => {
+            debug!("Could not list the pinned Flatpak runtimes: {e}");
+            Vec::new()
         }
-    } else {
-        let mut update_args = vec!["update", "--system"];
+    }
+}
+
+/// Refs of the runtimes installed in the given installation.
+fn flatpak_installed_runtimes(flatpak: &Path, installation: &str) -> Vec<String> {
+    match Command::new(flatpak)
+        .args(["list", "--runtime", installation, "--columns=ref"])
+        .output_checked_utf8()
+    {
+        Ok(output) => output
+            .stdout
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.contains('/'))
+            .map(String::from)
+            .collect(),
+        Err(e) => {
+            debug!("Could not list the Flatpak runtimes: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Run `flatpak update` for an installation (`--user` or `--system`). When it fails,
+/// the refs that still have pending updates are retried up to `flatpak.retry` times.
+///
+/// The installation is repaired first with `flatpak.repair`, or once after an
+/// update failing because of corrupt objects.
+fn flatpak_update(
+    ctx: &ExecutionContext,
+    flatpak: &Path,
+    sudo: Option<&crate::sudo::Sudo>,
+    installation: &str,
+    yes: bool,
+) -> Result<()> {
+    let mut program: Vec<OsString> = Vec::new();
+    if let Some(sudo) = sudo {
+        program.push(sudo.as_ref().into());
+    }
+    // Flatpak can't limit its bandwidth, `trickle` can do it for it
+    if let Some(limit) = ctx.config().bandwidth_limit() {
+        match which("trickle") {
+            Some(trickle) => program.extend([trickle.into(), "-s".into(), "-d".into(), limit.to_string().into()]),
+            None => debug!("trickle isn't installed, not limiting the bandwidth of Flatpak"),
+        }
+    }
+    program.push(flatpak.into());
+
+    let update = |refs: &[String]| {
+        let mut command = ctx.run_type().execute(&program[0]);
+        command.args(&program[1..]).args(["update", installation]);
         if yes {
-            update_args.push("-y");
+            command.arg("-y");
         }
-        run_type.execute(&flatpak).args(&update_args).status_checked()?;
-        if cleanup {
-            let mut cleanup_args = vec!["uninstall", "--system", "--unused"];
-            if yes {
-                cleanup_args.push("-y");
-            }
-            run_type.execute(flatpak).args(&cleanup_args).status_checked()?;
+        command.args(refs).status_checked_with_stderr()
+    };
+
+    let repair = || {
+        // Repairing doesn't download much, so it doesn't go through `trickle`
+        flatpak_command(ctx, flatpak, sudo)
+            .args(["repair", installation])
+            .status_checked()
+    };
+
+    if ctx.config().flatpak_repair() {
+        print_info(t!(
+            "Repairing the Flatpak installation ({installation})",
+            installation = installation
+        ));
+        repair()?;
+    }
+
+    let mut result = update(&[]);
+    if result.as_ref().is_err_and(is_flatpak_corruption) {
+        print_warning(t!(
+            "Flatpak found corrupt objects, repairing the installation ({installation}) and retrying",
+            installation = installation
+        ));
+        repair()?;
+        result = update(&[]);
+    }
+
+    let retries = ctx.config().flatpak_retry();
+    for attempt in 1..=retries {
+        let Err(e) = &result else {
+            break;
+        };
+
+        let refs = flatpak_pending_refs(flatpak, installation);
+        if refs.is_empty() {
+            break;
         }
+
+        debug!("Flatpak update failed: {e}");
+        print_warning(t!(
+            "Flatpak update failed, retrying {refs} ({attempt}/{retries})",
+            refs = refs.join(", "),
+            attempt = attempt,
+            retries = retries
+        ));
+        result = update(&refs);
     }
 
+    result
+}
+
+/// Whether a `flatpak update` failure was caused by corrupt objects in the repository.
+fn is_flatpak_corruption(e: &color_eyre::eyre::Error) -> bool {
+    matches!(
+        e.downcast_ref::<TopgradeError>(),
+        Some(TopgradeError::ProcessFailedWithOutput(_, _, stderr)) if stderr.to_lowercase().contains("corrupt")
+    )
+}
+
+/// Refs of the given installation that still have an update available.
+fn flatpak_pending_refs(flatpak: &Path, installation: &str) -> Vec<String> {
+    match Command::new(flatpak)
+        .args(["remote-ls", "--updates", installation, "--columns=ref"])
+        .output_checked_utf8()
+    {
+        Ok(output) => output
+            .stdout
+            .lines()
+            .map(str::trim)
+            // Skip the header, refs always contain a slash
+            .filter(|line| line.contains('/'))
+            .map(String::from)
+            .collect(),
+        Err(e) => {
+            debug!("Could not list pending Flatpak updates: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Refs masked with `flatpak mask` in the given installation (`--user` or `--system`).
+/// Flatpak never updates them, so they are reported as skipped.
+fn flatpak_masked_refs(flatpak: &Path, installation: &str) -> Vec<String> {
+    let output = match Command::new(flatpak).args(["mask", installation]).output_checked_utf8() {
+        Ok(output) => output,
+        Err(e) => {
+            debug!("Could not list masked Flatpak refs: {e}");
+            return Vec::new();
+        }
+    };
+
+    // The patterns are listed indented below a "Masked patterns:" header
+    let masked: Vec<String> = output
+        .stdout
+        .lines()
+        .filter(|line| line.starts_with(char::is_whitespace))
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    debug!("Masked Flatpak refs ({installation}): {masked:?}");
+    if !masked.is_empty() {
+        println!("{}", t!("Skipping masked refs: {refs}", refs = masked.join(", ")));
+    }
+
+    masked
+}
+
+/// `flatpak update` can fail when every pending update is masked, which
+/// isn't an actual failure of the step. Any other failure is returned.
+fn allow_masked_failure(result: Result<()>, flatpak: &Path, installation: &str, masked: &[String]) -> Result<()> {
+    let Err(e) = result else {
+        return Ok(());
+    };
+    if masked.is_empty() {
+        return Err(e);
+    }
+
+    let pending = flatpak_pending_refs(flatpak, installation);
+    if pending.is_empty() || !pending.iter().all(|flatpak_ref| is_masked(flatpak_ref, masked)) {
+        return Err(e);
+    }
+
+    debug!("Ignoring Flatpak update failure, all the pending refs are masked: {e}");
+    print_warning(t!("Flatpak update failed because all the pending updates are masked"));
     Ok(())
 }
 
+/// Whether one of the `flatpak mask` patterns matches a ref, like `app/org.gnome.Maps/x86_64/stable`.
+/// Patterns can match the whole ref or only its ID, e.g. `org.gnome.*`.
+fn is_masked(flatpak_ref: &str, patterns: &[String]) -> bool {
+    let id = flatpak_ref.split('/').nth(1).unwrap_or(flatpak_ref);
+    patterns
+        .iter()
+        .filter_map(|pattern| glob::Pattern::new(pattern).ok())
+        .any(|pattern| pattern.matches(id) || pattern.matches(flatpak_ref))
+}
+
+/// Whether the system asks for a reboot to finish applying updates.
+pub fn reboot_required() -> bool {
+    if Path::new("/var/run/reboot-required").exists() {
+        return true;
+    }
+
+    // `needs-restarting -r` exits with 1 when a reboot is needed on Red Hat-based distributions
+    which("needs-restarting").is_some_and(|needs_restarting| {
+        Command::new(needs_restarting)
+            .arg("-r")
+            .output_checked_with(|output| {
+                if output.status.code() == Some(1) {
+                    Ok(())
+                } else {
+                    Err(())
+                }
+            })
+            .is_ok()
+    })
+}
+
 pub fn run_snap(ctx: &ExecutionContext) -> Result<()> {
     let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
     let snap = require("snap")?;
@@ -947,7 +1998,62 @@ pub fn run_snap(ctx: &ExecutionContext) -> Result<()> {
     }
     print_separator("snap");
 
-    ctx.run_type().execute(sudo).arg(snap).arg("refresh").status_checked()
+    if ctx.config().bandwidth_limit().is_some() {
+        debug!("snap can't limit its bandwidth for a single refresh, not limiting it");
+    }
+
+    ctx.run_type()
+        .execute(sudo)
+        .arg(&snap)
+        .arg("refresh")
+        .status_checked()?;
+
+    if ctx.config().cleanup() && ctx.config().snap_remove_disabled() {
+        snap_remove_disabled(ctx, sudo, &snap)?;
+    }
+
+    Ok(())
+}
+
+/// Remove the disabled revisions kept by snap after refreshes, asking once with
+/// their list unless `--yes` is given and `snap.confirm_removals` is false.
+fn snap_remove_disabled(ctx: &ExecutionContext, sudo: &crate::sudo::Sudo, snap: &Path) -> Result<()> {
+    let output = Command::new(snap).args(["list", "--all"]).output_checked_utf8()?;
+    // Name  Version  Rev  Tracking  Publisher  Notes
+    let revisions: Vec<(&str, &str)> = output
+        .stdout
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let disabled = fields.last()?.split(',').any(|note| note == "disabled");
+            (disabled && fields.len() >= 3).then(|| (fields[0], fields[2]))
+        })
+        .collect();
+
+    if revisions.is_empty() {
+        return Ok(());
+    }
+
+    let list = revisions
+        .iter()
+        .map(|(name, revision)| format!("{name} ({revision})"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let should_ask = !ctx.config().dry_run() && (ctx.config().snap_confirm_removals() || !ctx.config().yes(Step::Snap));
+    if should_ask && !prompt_yesno(&t!("Remove the disabled snap revisions {revisions}?", revisions = list))? {
+        return Ok(());
+    }
+
+    for (name, revision) in revisions {
+        ctx.run_type()
+            .execute(sudo)
+            .arg(snap)
+            .args(["remove", name, &format!("--revision={revision}")])
+            .status_checked()?;
+    }
+
+    Ok(())
 }
 
 pub fn run_pihole_update(ctx: &ExecutionContext) -> Result<()> {
@@ -1288,4 +2394,59 @@ mod tests {
     fn test_nilrt() {
         test_template(include_str!("os_release/nilrt"), Distribution::NILRT);
     }
+
+    #[test]
+    fn test_flatpak_is_masked() {
+        let masked = vec![
+            String::from("org.gnome.*"),
+            String::from("runtime/org.kde.Platform/*/5.15"),
+        ];
+        assert!(is_masked("app/org.gnome.Maps/x86_64/stable", &masked));
+        assert!(is_masked("runtime/org.kde.Platform/x86_64/5.15", &masked));
+        assert!(!is_masked("runtime/org.kde.Platform/x86_64/6.7", &masked));
+        assert!(!is_masked("app/org.mozilla.firefox/x86_64/stable", &masked));
+    }
+
+    #[test]
+    fn test_flatpak_removed_runtimes() {
+        let before = vec![
+            String::from("org.freedesktop.Platform/x86_64/23.08"),
+            String::from("org.freedesktop.Platform/x86_64/24.08"),
+            String::from("org.freedesktop.Platform.GL.default/x86_64/23.08"),
+            String::from("org.gnome.Platform/x86_64/46"),
+        ];
+        let after = vec![
+            String::from("org.freedesktop.Platform/x86_64/24.08"),
+            String::from("org.gnome.Platform/x86_64/46"),
+            String::from("org.gnome.Platform/x86_64/47"),
+        ];
+        assert_eq!(
+            removed_runtimes(before, &after),
+            vec![
+                "org.freedesktop.Platform/x86_64/23.08",
+                "org.freedesktop.Platform.GL.default/x86_64/23.08"
+            ]
+        );
+        assert!(removed_runtimes(after.clone(), &after).is_empty());
+    }
+
+    #[test]
+    fn test_dpkg_interrupted() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::ExitStatus;
+
+        let failure = |stderr: &str| {
+            color_eyre::eyre::Error::from(TopgradeError::ProcessFailedWithOutput(
+                String::from("apt-get"),
+                ExitStatus::from_raw(100 << 8),
+                String::from(stderr),
+            ))
+        };
+        assert!(dpkg_interrupted(&failure(
+            "E: dpkg was interrupted, you must manually run 'sudo dpkg --configure -a' to correct the problem. \n"
+        )));
+        assert!(!dpkg_interrupted(&failure(
+            "E: Could not get lock /var/lib/dpkg/lock-frontend. It is held by process 1234 (apt-get)\n"
+        )));
+    }
 }
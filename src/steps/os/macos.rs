@@ -1,15 +1,22 @@
 use crate::command::CommandExt;
+use crate::error::{NoChanges, PartialFailure, SkipStep, Updated};
 use crate::execution_context::ExecutionContext;
-use crate::terminal::{print_separator, prompt_yesno};
-use crate::utils::{get_require_sudo_string, require_option};
+use crate::terminal::{print_separator, print_warning, prompt_yesno};
+use crate::utils::{get_require_sudo_string, require_option, which};
 use crate::{utils::require, Step};
 use color_eyre::eyre::Result;
 use rust_i18n::t;
 use std::collections::HashSet;
 use std::fs;
+use std::path::Path;
 use std::process::Command;
 use tracing::debug;
 
+/// Update MacPorts and upgrade the outdated ports.
+///
+/// The outdated ports are listed with `port outdated` even in dry runs, since it
+/// changes nothing. Only its English "No installed ports are outdated" message is
+/// recognized, other output is taken as outdated ports.
 pub fn run_macports(ctx: &ExecutionContext) -> Result<()> {
     require("port")?;
     let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
@@ -19,10 +26,23 @@ pub fn run_macports(ctx: &ExecutionContext) -> Result<()> {
         .execute(sudo)
         .args(["port", "selfupdate"])
         .status_checked()?;
-    ctx.run_type()
-        .execute(sudo)
-        .args(["port", "-u", "upgrade", "outdated"])
-        .status_checked()?;
+
+    let outdated = Command::new("port").arg("outdated").output_checked_utf8()?;
+    let up_to_date = outdated.stdout.contains("No installed ports are outdated");
+
+    if !up_to_date {
+        let mut command = ctx.run_type().execute(sudo);
+        command.arg("port");
+        if ctx.config().yes(Step::Macports) {
+            command.arg("-N");
+        }
+        command.args(["-u", "upgrade"]);
+        if let Some(args) = ctx.config().macports_args() {
+            command.args(args.split_whitespace());
+        }
+        command.arg("outdated").status_checked()?;
+    }
+
     if ctx.config().cleanup() {
         ctx.run_type()
             .execute(sudo)
@@ -30,13 +50,31 @@ pub fn run_macports(ctx: &ExecutionContext) -> Result<()> {
             .status_checked()?;
     }
 
+    // The step still updated MacPorts itself
+    if up_to_date {
+        return Err(NoChanges.into());
+    }
+
     Ok(())
 }
 
+/// Upgrade the App Store apps, if `mas outdated` lists some or with `macos.mas_force`.
+///
+/// `mas outdated` runs even in dry runs, since it changes nothing. Its output is
+/// only checked for being empty, which doesn't depend on the language.
 pub fn run_mas(ctx: &ExecutionContext) -> Result<()> {
     let mas = require("mas")?;
     print_separator(t!("macOS App Store"));
 
+    if !ctx.config().mas_force() {
+        let outdated = Command::new(&mas).arg("outdated").output_checked_utf8()?;
+        let outdated = outdated.stdout.trim();
+        if outdated.is_empty() {
+            return Err(NoChanges.into());
+        }
+        println!("{outdated}");
+    }
+
     ctx.run_type().execute(mas).arg("upgrade").status_checked()
 }
 
@@ -68,6 +106,66 @@ pub fn upgrade_macos(ctx: &ExecutionContext) -> Result<()> {
     command.status_checked()
 }
 
+/// Install the Command Line Tools updates, which `softwareupdate` lists separately from
+/// the system updates. Xcode itself can only be updated from the App Store.
+pub fn update_command_line_tools(ctx: &ExecutionContext) -> Result<()> {
+    let output = Command::new("softwareupdate").arg("--list").output_checked_utf8()?;
+    // e.g. `* Label: Command Line Tools for Xcode-15.3`
+    let labels: Vec<&str> = output
+        .stdout
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("* Label: "))
+        .filter(|label| label.starts_with("Command Line Tools"))
+        .collect();
+    let xcode = xcode_update();
+
+    if labels.is_empty() && xcode.is_none() {
+        return Err(SkipStep(t!("Nothing to upgrade").to_string()).into());
+    }
+
+    print_separator(t!("Command Line Tools"));
+
+    if let Some(version) = xcode {
+        print_warning(t!(
+            "Xcode {version} is available, update it from the App Store",
+            version = version
+        ));
+    }
+
+    let should_ask = !(ctx.config().accept_all_macos_updates()
+        || ctx.config().yes(Step::CommandLineTools)
+        || ctx.config().dry_run());
+    for label in labels {
+        if should_ask && !prompt_yesno(t!("Install {label}?", label = label).as_ref())? {
+            continue;
+        }
+        ctx.run_type()
+            .execute("softwareupdate")
+            .args(["--install", label])
+            .status_checked()?;
+    }
+
+    Ok(())
+}
+
+/// The version of the Xcode update in the App Store, according to `mas outdated`.
+fn xcode_update() -> Option<String> {
+    let mas = which("mas")?;
+    let output = Command::new(mas)
+        .arg("outdated")
+        .output_checked_utf8()
+        .map_err(|e| debug!("Could not list the outdated App Store apps: {e}"))
+        .ok()?;
+
+    // e.g. `497799835 Xcode (15.2 -> 15.3)`
+    output
+        .stdout
+        .lines()
+        .find(|line| line.split_whitespace().nth(1) == Some("Xcode"))
+        .and_then(|line| line.split("-> ").nth(1))
+        .map(|version| version.trim_end_matches(')').to_string())
+}
+
 fn system_update_available() -> Result<bool> {
     let output = Command::new("softwareupdate").arg("--list").output_checked_utf8()?;
 
@@ -79,21 +177,80 @@ fn system_update_available() -> Result<bool> {
 pub fn run_sparkle(ctx: &ExecutionContext) -> Result<()> {
     let sparkle = require("sparkle")?;
 
+    let allowed = ctx.config().sparkle_apps();
+
     print_separator("Sparkle");
 
+    let mut updated = Vec::new();
+    let mut failed = Vec::new();
     for application in (fs::read_dir("/Applications")?).flatten() {
+        let path = application.path();
+        let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        if allowed.is_some_and(|allowed| !allowed.iter().any(|app| app.trim_end_matches(".app") == name)) {
+            continue;
+        }
+
         let probe = Command::new(&sparkle)
             .args(["--probe", "--application"])
-            .arg(application.path())
+            .arg(&path)
             .output_checked_utf8();
-        if probe.is_ok() {
-            let mut command = ctx.run_type().execute(&sparkle);
-            command.args(["bundle", "--check-immediately", "--application"]);
-            command.arg(application.path());
-            command.status_checked()?;
+        if probe.is_err() {
+            continue;
+        }
+
+        let before = app_version(&path);
+        let mut command = ctx.run_type().execute(&sparkle);
+        command.args(["bundle", "--check-immediately", "--application"]);
+        command.arg(&path);
+        if let Err(e) = command.status_checked() {
+            debug!("Sparkle failed to update {name}: {e}");
+            failed.push(name);
+            continue;
+        }
+
+        let after = app_version(&path);
+        if before != after {
+            let mut change = format!(
+                "{name}: {} -> {}",
+                before.as_deref().unwrap_or("?"),
+                after.as_deref().unwrap_or("?")
+            );
+            // The running instance keeps using the old version
+            if is_app_running(&path) {
+                print_warning(t!("{app} needs to be restarted to use the new version", app = name));
+                change.push_str(&format!(" ({})", t!("restart required")));
+            }
+            updated.push(change);
         }
     }
-    Ok(())
+
+    if !failed.is_empty() {
+        Err(PartialFailure(failed).into())
+    } else if updated.is_empty() {
+        Err(NoChanges.into())
+    } else {
+        Err(Updated(updated).into())
+    }
+}
+
+/// The version of an application bundle, from its `Info.plist`.
+fn app_version(app: &Path) -> Option<String> {
+    Command::new("defaults")
+        .arg("read")
+        .arg(app.join("Contents/Info"))
+        .arg("CFBundleShortVersionString")
+        .output_checked_utf8()
+        .ok()
+        .map(|output| output.stdout.trim().to_string())
+}
+
+/// Whether an executable of the application bundle is running.
+fn is_app_running(app: &Path) -> bool {
+    Command::new("pgrep")
+        .arg("-f")
+        .arg(app.join("Contents/MacOS/"))
+        .output_checked_utf8()
+        .is_ok()
 }
 
 pub fn update_xcodes(ctx: &ExecutionContext) -> Result<()> {
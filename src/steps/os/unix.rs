@@ -24,12 +24,13 @@ use tracing::debug;
 #[cfg(target_os = "linux")]
 use super::linux::Distribution;
 use crate::error::SkipStep;
+use crate::error::TopgradeError;
+use crate::error::Updated;
 use crate::execution_context::ExecutionContext;
 #[cfg(any(target_os = "linux", target_os = "macos"))]
-use crate::executor::Executor;
-#[cfg(any(target_os = "linux", target_os = "macos"))]
 use crate::executor::RunType;
-use crate::terminal::print_separator;
+use crate::executor::{Executor, ExecutorOutput};
+use crate::terminal::{print_info, print_separator, print_warning};
 use crate::utils::{get_require_sudo_string, require, require_option, PathExt};
 
 #[cfg(any(target_os = "linux", target_os = "macos"))]
@@ -260,6 +261,13 @@ pub fn upgrade_gnome_extensions(ctx: &ExecutionContext) -> Result<()> {
 
     print_separator(t!("Gnome Shell extensions"));
 
+    // The DBus method only queues the updates for the next login, gext applies them right away
+    if !ctx.config().gnome_extensions_use_dbus() {
+        if let Some(gext) = crate::utils::which("gext").or_else(|| crate::utils::which("gnome-extensions-cli")) {
+            return ctx.run_type().execute(gext).arg("update").status_checked();
+        }
+    }
+
     ctx.run_type()
         .execute(gdbus)
         .args([
@@ -334,25 +342,225 @@ pub fn run_brew_formula(ctx: &ExecutionContext, variant: BrewVariant) -> Result<
     print_separator(variant.step_title());
     let run_type = ctx.run_type();
 
-    variant.execute(run_type).arg("update").status_checked()?;
+    let verbosity = match (ctx.config().brew_quiet(), ctx.config().brew_verbose()) {
+        (true, true) => {
+            return Err(eyre!(
+                "{}",
+                t!("`brew.quiet` and `brew.verbose` cannot be used together")
+            ))
+        }
+        (true, false) => Some("--quiet"),
+        (false, true) => Some("--verbose"),
+        (false, false) => None,
+    };
+
+    variant
+        .execute(run_type)
+        .arg("update")
+        .args(verbosity)
+        .status_checked()?;
 
-    let mut command = variant.execute(run_type);
-    command.args(["upgrade", "--formula"]);
+    let pinned = brew_pinned_formulae(variant);
+    if !pinned.is_empty() {
+        println!(
+            "{}",
+            t!("Skipping pinned formulae: {formulae}", formulae = pinned.join(" "))
+        );
+    }
 
-    if ctx.config().brew_fetch_head() {
-        command.arg("--fetch-HEAD");
+    if run_type.dry() {
+        print_brew_outdated(variant, "--formula");
     }
 
-    command.status_checked()?;
+    let upgrade = || {
+        let mut command = variant.execute(run_type);
+        command.args(["upgrade", "--formula"]).args(verbosity);
+        // `brew update` just ran, there's no need for `brew upgrade` to run it again
+        command.env("HOMEBREW_NO_AUTO_UPDATE", "1");
+
+        if ctx.config().brew_fetch_head() {
+            command.arg("--fetch-HEAD");
+        }
+
+        command.status_checked_with_stderr()
+    };
+
+    if let Err(e) = upgrade() {
+        let conflicts = brew_link_conflicts(&e);
+        if !ctx.config().brew_force_link() || conflicts.is_empty() {
+            return Err(e);
+        }
+
+        for formula in &conflicts {
+            variant
+                .execute(run_type)
+                .args(["link", "--overwrite", formula])
+                .status_checked()?;
+        }
+        upgrade().wrap_err_with(|| {
+            t!(
+                "Upgrading failed again after linking {formulae} with --overwrite, the first attempt failed with: {error}",
+                formulae = conflicts.join(" "),
+                error = e
+            )
+            .to_string()
+        })?;
+    }
 
     if ctx.config().cleanup() {
         variant.execute(run_type).arg("cleanup").status_checked()?;
     }
 
-    if ctx.config().brew_autoremove() {
+    if ctx.config().brew_autoremove_dry_run() {
+        variant
+            .execute(run_type)
+            .args(["autoremove", "--dry-run"])
+            .status_checked()?;
+    } else if ctx.config().brew_autoremove() {
         variant.execute(run_type).arg("autoremove").status_checked()?;
     }
 
+    if ctx.config().brew_restart_services() {
+        restart_brew_services(ctx, variant)?;
+    }
+
+    Ok(())
+}
+
+/// Formulae that `brew upgrade` couldn't link because of existing files, from the
+/// `brew link --overwrite <formula>` hints of its error.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn brew_link_conflicts(e: &color_eyre::eyre::Error) -> Vec<String> {
+    let Some(TopgradeError::ProcessFailedWithOutput(_, _, stderr)) = e.downcast_ref::<TopgradeError>() else {
+        return Vec::new();
+    };
+    if !stderr.contains("Could not symlink") {
+        return Vec::new();
+    }
+
+    let mut formulae: Vec<String> = Vec::new();
+    for line in stderr.lines() {
+        let Some((_, args)) = line.split_once("brew link --overwrite ") else {
+            continue;
+        };
+        // Skip the `--dry-run` hint
+        if let Some(formula) = args.split_whitespace().find(|arg| !arg.starts_with('-')) {
+            if !formulae.iter().any(|f| f == formula) {
+                formulae.push(formula.to_string());
+            }
+        }
+    }
+    formulae
+}
+
+/// Formulae pinned with `brew pin`, which `brew upgrade` silently leaves alone.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn brew_pinned_formulae(variant: BrewVariant) -> Vec<String> {
+    match variant
+        .execute(RunType::Wet)
+        .args(["list", "--pinned"])
+        .output_checked_utf8()
+    {
+        Ok(output) => output.stdout.split_whitespace().map(String::from).collect(),
+        Err(e) => {
+            debug!("Could not list pinned formulae: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Print the outdated formulae or casks (`kind` is `--formula` or `--cask`) with their
+/// version changes, as a preview of what a dry run would upgrade.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn print_brew_outdated(variant: BrewVariant, kind: &str) {
+    #[derive(serde::Deserialize)]
+    struct Outdated {
+        #[serde(default)]
+        formulae: Vec<Package>,
+        #[serde(default)]
+        casks: Vec<Package>,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Package {
+        name: String,
+        installed_versions: Vec<String>,
+        current_version: String,
+    }
+
+    let list_outdated = || -> Result<Outdated> {
+        let output = variant
+            .execute(RunType::Wet)
+            .args(["outdated", "--json=v2", kind])
+            .output_checked_utf8()?;
+        Ok(serde_json::from_str(&output.stdout)?)
+    };
+    let outdated = match list_outdated() {
+        Ok(outdated) => outdated,
+        Err(e) => {
+            debug!("Could not list outdated packages: {e}");
+            return;
+        }
+    };
+
+    let packages: Vec<Package> = outdated.formulae.into_iter().chain(outdated.casks).collect();
+    if packages.is_empty() {
+        println!("{}", t!("Nothing to upgrade"));
+        return;
+    }
+
+    println!("{}", t!("Would upgrade:"));
+    for package in packages {
+        println!(
+            "  {} {} -> {}",
+            package.name,
+            package.installed_versions.join(", "),
+            package.current_version
+        );
+    }
+}
+
+/// Restart the running Brew services so that they pick up the upgraded formulae.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn restart_brew_services(ctx: &ExecutionContext, variant: BrewVariant) -> Result<()> {
+    let output = variant
+        .execute(RunType::Wet)
+        .args(["services", "list"])
+        .output_checked_utf8()?;
+    let configured = ctx.config().brew_services();
+
+    // The first line is the `Name Status User File` header
+    let services: Vec<&str> = output
+        .stdout
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let name = columns.next()?;
+            (columns.next()? == "started").then_some(name)
+        })
+        .filter(|name| match configured {
+            Some(configured) => configured.iter().any(|s| s == name),
+            None => true,
+        })
+        .collect();
+
+    if services.is_empty() {
+        debug!("No running Brew services to restart");
+        return Ok(());
+    }
+
+    for service in &services {
+        variant
+            .execute(ctx.run_type())
+            .args(["services", "restart", service])
+            .status_checked()?;
+    }
+    print_info(t!(
+        "Restarted Brew services: {services}",
+        services = services.join(", ")
+    ));
+
     Ok(())
 }
 
@@ -390,6 +598,14 @@ pub fn run_brew_cask(ctx: &ExecutionContext, variant: BrewVariant) -> Result<()>
             brew_args.push("--greedy-auto-updates");
         }
     }
+    // Both `brew cu` and `brew upgrade --cask` support it
+    if ctx.config().brew_cask_no_quarantine() {
+        brew_args.push("--no-quarantine");
+    }
+
+    if run_type.dry() {
+        print_brew_outdated(variant, "--cask");
+    }
 
     variant.execute(run_type).args(&brew_args).status_checked()?;
 
@@ -444,6 +660,23 @@ pub fn run_nix(ctx: &ExecutionContext) -> Result<()> {
     let run_type = ctx.run_type();
     run_type.execute(nix_channel).arg("--update").status_checked()?;
 
+    if ctx.config().nix_update_flake_inputs() {
+        let flake_dir = ctx.config().nix_flake_dir();
+        if flake_dir.join("flake.nix").exists() {
+            run_type
+                .execute(&nix)
+                .args(nix_args())
+                .args(["flake", "update"])
+                .current_dir(&flake_dir)
+                .status_checked()?;
+        } else {
+            print_warning(t!(
+                "No flake.nix found in {flake_dir}, not updating flake inputs",
+                flake_dir = flake_dir.display()
+            ));
+        }
+    }
+
     let mut get_version_cmd = ctx.run_type().execute(&nix);
     get_version_cmd.arg("--version");
     let get_version_cmd_output = get_version_cmd.output_checked_utf8()?;
@@ -494,22 +727,112 @@ pub fn run_nix(ctx: &ExecutionContext) -> Result<()> {
         vec![".*"]
     };
 
+    // The store path of the current generation of the profile
+    let before = fs::canonicalize(&profile_path).ok();
+
     if Path::new(&manifest_json_path).exists() {
         run_type
-            .execute(nix)
+            .execute(&nix)
             .args(nix_args())
             .arg("profile")
             .arg("upgrade")
             .args(&packages)
             .arg("--verbose")
-            .status_checked()
+            .status_checked()?;
     } else {
         let mut command = run_type.execute(nix_env);
         command.arg("--upgrade");
         if let Some(args) = ctx.config().nix_env_arguments() {
             command.args(args.split_whitespace());
         };
-        command.status_checked()
+        command.status_checked()?;
+    }
+
+    let changes = match before {
+        Some(before) if ctx.config().nix_show_diff() && !run_type.dry() => {
+            nix_profile_changes(&nix, &before, &profile_path)
+        }
+        _ => Vec::new(),
+    };
+
+    if ctx.config().cleanup() {
+        run_type
+            .execute(require("nix-collect-garbage")?)
+            .args(nix_collect_garbage_args(ctx))
+            .status_checked()?;
+
+        if ctx.config().nix_optimise() {
+            optimise_nix_store(run_type.execute(&nix))?;
+        }
+    }
+
+    if changes.is_empty() {
+        Ok(())
+    } else {
+        Err(Updated(changes).into())
+    }
+}
+
+/// The packages whose version changed between the `before` generation of the profile
+/// and its current one, according to `nix store diff-closures`, e.g. `hello: 2.10 → 2.12.1`.
+fn nix_profile_changes(nix: &Path, before: &Path, profile_path: &Path) -> Vec<String> {
+    let after = match fs::canonicalize(profile_path) {
+        Ok(after) if after != before => after,
+        _ => return Vec::new(),
+    };
+
+    let output = Command::new(nix)
+        .args(nix_args())
+        .args(["store", "diff-closures"])
+        .arg(before)
+        .arg(after)
+        .output_checked_utf8();
+    match output {
+        // Lines are `hello: 2.10 → 2.12.1, +12.3 KiB`, or only the size when the version didn't change
+        Ok(output) => output
+            .stdout
+            .lines()
+            .filter(|line| line.contains('→'))
+            .map(|line| line.split(", ").next().unwrap_or(line).trim().to_string())
+            .collect(),
+        Err(e) => {
+            debug!("Could not compare the Nix profile generations: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Run `nix store optimise` with `command`, which runs `nix`, and report the space it freed.
+pub fn optimise_nix_store(mut command: Executor) -> Result<()> {
+    command.args(nix_args()).args(["store", "optimise"]);
+    let ExecutorOutput::Wet(output) = command.output()? else {
+        return Ok(());
+    };
+    if !output.status.success() {
+        return Err(TopgradeError::ProcessFailedWithOutput(
+            command.get_program(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+        .into());
+    }
+
+    // Nix reports e.g. `12.34 MiB freed by hard-linking 567 files`
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match stderr.lines().find(|line| line.contains("freed")) {
+        Some(freed) => println!("{}", t!("Nix store optimised: {freed}", freed = freed.trim())),
+        None => debug!("`nix store optimise` didn't report the freed space: {stderr}"),
+    }
+
+    Ok(())
+}
+
+/// Arguments for `nix-collect-garbage`, deleting the old generations
+/// except the ones younger than `nix.gc_keep_days`.
+pub fn nix_collect_garbage_args(ctx: &ExecutionContext) -> Vec<String> {
+    match ctx.config().nix_gc_keep_days() {
+        Some(days) => vec![String::from("--delete-older-than"), format!("{days}d")],
+        None => vec![String::from("-d")],
     }
 }
 
@@ -668,7 +991,27 @@ pub fn run_asdf(ctx: &ExecutionContext) -> Result<()> {
     ctx.run_type()
         .execute(&asdf)
         .args(["plugin", "update", "--all"])
-        .status_checked()
+        .status_checked()?;
+
+    if ctx.config().asdf_install_latest() {
+        for tool in ctx.config().asdf_tools() {
+            ctx.run_type()
+                .execute(&asdf)
+                .args(["install", tool, "latest"])
+                .status_checked()?;
+
+            // asdf 0.16.0 replaced `asdf global` with `asdf set --home`
+            let mut command = ctx.run_type().execute(&asdf);
+            if version < Version::new(0, 16, 0) {
+                command.arg("global");
+            } else {
+                command.args(["set", "--home"]);
+            }
+            command.args([tool, "latest"]).status_checked()?;
+        }
+    }
+
+    Ok(())
 }
 
 pub fn run_mise(ctx: &ExecutionContext) -> Result<()> {
@@ -776,11 +1119,24 @@ pub fn run_sdkman(ctx: &ExecutionContext) -> Result<()> {
         .args(["-c", cmd_update.as_str()])
         .status_checked()?;
 
-    let cmd_upgrade = format!("source {} && sdk upgrade", &sdkman_init_path);
-    ctx.run_type()
-        .execute(&bash)
-        .args(["-c", cmd_upgrade.as_str()])
-        .status_checked()?;
+    match ctx.config().sdkman_candidates() {
+        Some(candidates) if !candidates.is_empty() => {
+            for candidate in candidates {
+                let cmd_upgrade = format!("source {} && sdk upgrade {}", &sdkman_init_path, candidate);
+                ctx.run_type()
+                    .execute(&bash)
+                    .args(["-c", cmd_upgrade.as_str()])
+                    .status_checked()?;
+            }
+        }
+        _ => {
+            let cmd_upgrade = format!("source {} && sdk upgrade", &sdkman_init_path);
+            ctx.run_type()
+                .execute(&bash)
+                .args(["-c", cmd_upgrade.as_str()])
+                .status_checked()?;
+        }
+    }
 
     if ctx.config().cleanup() {
         let cmd_flush_archives = format!("source {} && sdk flush archives", &sdkman_init_path);
@@ -839,3 +1195,52 @@ pub fn reboot() -> Result<()> {
 
     Command::new("sudo").arg("reboot").status_checked()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_brew_link_conflicts() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::ExitStatus;
+
+        let failure = |stderr: &str| {
+            color_eyre::eyre::Error::from(TopgradeError::ProcessFailedWithOutput(
+                String::from("brew"),
+                ExitStatus::from_raw(1 << 8),
+                String::from(stderr),
+            ))
+        };
+
+        let stderr = "\
+Error: The `brew link` step did not complete successfully
+The formula built, but is not symlinked into /opt/homebrew
+Could not symlink bin/node
+Target /opt/homebrew/bin/node
+already exists. You may want to remove it:
+  rm '/opt/homebrew/bin/node'
+
+To force the link and overwrite all conflicting files:
+  brew link --overwrite node
+
+To list all files that would be deleted:
+  brew link --overwrite --dry-run node
+Error: The `brew link` step did not complete successfully
+The formula built, but is not symlinked into /opt/homebrew
+Could not symlink bin/2to3-3.12
+Target /opt/homebrew/bin/2to3-3.12
+already exists. You may want to remove it:
+  rm '/opt/homebrew/bin/2to3-3.12'
+
+To force the link and overwrite all conflicting files:
+  brew link --overwrite python@3.12
+
+To list all files that would be deleted:
+  brew link --overwrite python@3.12 --dry-run
+";
+        assert_eq!(brew_link_conflicts(&failure(stderr)), vec!["node", "python@3.12"]);
+        assert!(brew_link_conflicts(&failure("Error: No such keg: /opt/homebrew/Cellar/node\n")).is_empty());
+    }
+}
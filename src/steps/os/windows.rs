@@ -1,4 +1,5 @@
-use std::path::Path;
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
 use std::{ffi::OsStr, process::Command};
 
 use color_eyre::eyre::Result;
@@ -6,9 +7,10 @@ use etcetera::base_strategy::BaseStrategy;
 use tracing::debug;
 
 use crate::command::CommandExt;
+use crate::error::{PartialFailure, RebootRequired};
 use crate::execution_context::ExecutionContext;
-use crate::terminal::{print_separator, print_warning};
-use crate::utils::{require, which};
+use crate::terminal::{print_info, print_separator, print_warning};
+use crate::utils::{require, require_option, which};
 use crate::{error::SkipStep, steps::git::RepoStep};
 use crate::{powershell, Step};
 use rust_i18n::t;
@@ -19,13 +21,18 @@ pub fn run_chocolatey(ctx: &ExecutionContext) -> Result<()> {
 
     print_separator("Chocolatey");
 
+    let pinned = choco_pinned_packages(&choco);
+    if !pinned.is_empty() {
+        print_info(t!("Skipping pinned packages: {packages}", packages = pinned.join(", ")));
+    }
+
     let mut command = match ctx.sudo() {
         Some(sudo) => {
             let mut command = ctx.run_type().execute(sudo);
-            command.arg(choco);
+            command.arg(&choco);
             command
         }
-        None => ctx.run_type().execute(choco),
+        None => ctx.run_type().execute(&choco),
     };
 
     command.args(["upgrade", "all"]);
@@ -34,7 +41,38 @@ pub fn run_chocolatey(ctx: &ExecutionContext) -> Result<()> {
         command.arg("--yes");
     }
 
-    command.status_checked()
+    // Skipping the checksums is only allowed when all the updates are accepted anyway
+    if ctx.config().choco_ignore_checksums() && ctx.config().accept_all_windows_updates() {
+        command.arg("--ignore-checksums");
+    }
+
+    let exclude = ctx.config().choco_exclude();
+    if !exclude.is_empty() {
+        command.arg(format!("--except={}", exclude.join(",")));
+    }
+
+    if ctx.run_type().dry() {
+        print_choco_outdated(&choco);
+    }
+
+    // Chocolatey exits with 3010 (or 1641 when the reboot was started) when a
+    // package needs a reboot to finish installing
+    let reboot_required = Cell::new(false);
+    command.status_checked_with(|status| match status.code() {
+        Some(0) => Ok(()),
+        Some(1641 | 3010) => {
+            reboot_required.set(true);
+            Ok(())
+        }
+        _ => Err(()),
+    })?;
+
+    if reboot_required.get() {
+        print_warning(t!("Chocolatey needs a reboot to finish the upgrades"));
+        return Err(RebootRequired.into());
+    }
+
+    Ok(())
 }
 
 pub fn run_winget(ctx: &ExecutionContext) -> Result<()> {
@@ -42,9 +80,91 @@ pub fn run_winget(ctx: &ExecutionContext) -> Result<()> {
 
     print_separator("winget");
 
+    if ctx.run_type().dry() {
+        print_winget_upgradable(&winget);
+    }
+
+    let mut command = ctx.run_type().execute(&winget);
+    command.args(["upgrade", "--all"]);
+    if ctx.config().winget_silent() {
+        command.args(["--disable-interactivity", "--silent"]);
+        if ctx.config().accept_all_windows_updates() {
+            command.args(["--accept-package-agreements", "--accept-source-agreements"]);
+        }
+    }
+
+    // winget can exit successfully when only some of the packages failed,
+    // so the output is parsed to report them
+    let stdout = command.status_checked_with_stdout()?;
+    if ctx.run_type().dry() {
+        return Ok(());
+    }
+
+    warn_stale_path();
+
+    let failed = winget_failed_packages(&stdout);
+    if !failed.is_empty() {
+        return Err(PartialFailure(failed).into());
+    }
+
+    Ok(())
+}
+
+/// Packages that failed to upgrade, according to the output of `winget upgrade --all`.
+///
+/// Each package starts with a `(1/3) Found Name [Id] Version x.y` line, followed by
+/// its download and install messages. Only the English messages are recognized.
+fn winget_failed_packages(output: &str) -> Vec<String> {
+    let mut failed = Vec::new();
+    let mut current = None;
+
+    // Progress bars are redrawn with carriage returns
+    for line in output.split(['\r', '\n']).map(str::trim) {
+        if let Some((_, found)) = line.split_once("Found ") {
+            current = Some(found.split(" Version ").next().unwrap_or(found).trim().to_string());
+        } else if ["failed", "hash does not match"]
+            .iter()
+            .any(|message| line.to_lowercase().contains(message))
+        {
+            if let Some(package) = current.take() {
+                failed.push(package);
+            }
+        }
+    }
+
+    failed
+}
+
+pub fn run_winget_export(ctx: &ExecutionContext) -> Result<()> {
+    let winget = require("winget")?;
+    let path = require_option(
+        ctx.config().winget_export_path(),
+        String::from("No winget export path configured"),
+    )?;
+
+    print_separator("winget export");
+
+    ctx.run_type()
+        .execute(winget)
+        .args(["export", "-o"])
+        .arg(path)
+        .status_checked()
+}
+
+pub fn run_winget_configure(ctx: &ExecutionContext) -> Result<()> {
+    let winget = require("winget")?;
+    let file = require_option(
+        ctx.config().winget_configure_file(),
+        String::from("No winget configuration file configured"),
+    )?;
+
+    print_separator("winget configure");
+
     ctx.run_type()
         .execute(winget)
-        .args(["upgrade", "--all"])
+        .args(["configure", "-f"])
+        .arg(file)
+        .arg("--accept-configuration-agreements")
         .status_checked()
 }
 
@@ -54,8 +174,26 @@ pub fn run_scoop(ctx: &ExecutionContext) -> Result<()> {
     print_separator("Scoop");
 
     ctx.run_type().execute(&scoop).args(["update"]).status_checked()?;
+
+    if ctx.run_type().dry() {
+        print_scoop_outdated(&scoop);
+    }
+
     ctx.run_type().execute(&scoop).args(["update", "*"]).status_checked()?;
 
+    if ctx.config().scoop_global() && has_global_scoop_apps() {
+        if is_elevated() {
+            ctx.run_type()
+                .execute(&scoop)
+                .args(["update", "-g", "*"])
+                .status_checked()?;
+        } else {
+            print_warning(t!(
+                "Skipping the global Scoop apps, updating them requires running Topgrade as administrator"
+            ));
+        }
+    }
+
     if ctx.config().cleanup() {
         ctx.run_type().execute(&scoop).args(["cleanup", "*"]).status_checked()?;
         ctx.run_type()
@@ -64,9 +202,254 @@ pub fn run_scoop(ctx: &ExecutionContext) -> Result<()> {
             .status_checked()?
     }
 
+    if !ctx.run_type().dry() {
+        warn_stale_path();
+    }
+
     Ok(())
 }
 
+/// Warn about the directories that upgrades added to the user or system `PATH`, which
+/// the current shell doesn't have yet: the programs they contain aren't found until
+/// the shell is restarted.
+fn warn_stale_path() {
+    let normalize = |dir: &str| dir.trim_end_matches('\\').to_lowercase();
+    let current: Vec<String> = std::env::var_os("PATH")
+        .map(|path| {
+            std::env::split_paths(&path)
+                .map(|dir| normalize(&dir.to_string_lossy()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let missing: Vec<String> = [
+        r"HKCU\Environment",
+        r"HKLM\SYSTEM\CurrentControlSet\Control\Session Manager\Environment",
+    ]
+    .into_iter()
+    .flat_map(registry_path)
+    .filter(|dir| !current.contains(&normalize(dir)))
+    .collect();
+
+    if !missing.is_empty() {
+        print_warning(t!(
+            "Restart your shell to use the programs in the directories added to PATH: {directories}",
+            directories = missing.join(", ")
+        ));
+    }
+}
+
+/// The directories of the `Path` value of a registry key, with the variables expanded.
+fn registry_path(key: &str) -> Vec<String> {
+    match Command::new("reg")
+        .args(["query", key, "/v", "Path"])
+        .output_checked_utf8()
+    {
+        // The value is printed as `    Path    REG_EXPAND_SZ    C:\a;%USERPROFILE%\b`
+        Ok(output) => output
+            .stdout
+            .lines()
+            .filter_map(|line| line.split_once("REG_EXPAND_SZ").or_else(|| line.split_once("REG_SZ")))
+            .flat_map(|(_, value)| value.trim().split(';').map(str::to_string).collect::<Vec<_>>())
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| expand_env_vars(&dir))
+            .collect(),
+        Err(e) => {
+            debug!("Could not read the PATH of {key}: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Expand the `%VARIABLE%`s of `value`, keeping the unknown ones.
+fn expand_env_vars(value: &str) -> String {
+    let parts: Vec<&str> = value.split('%').collect();
+    let mut expanded = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        // Variable names are between two `%`, so at odd positions before the last part
+        if i % 2 == 1 && i + 1 < parts.len() {
+            match std::env::var(part) {
+                Ok(var) => expanded.push_str(&var),
+                Err(_) => expanded.push_str(&format!("%{part}%")),
+            }
+        } else {
+            // A `%` without a closing one
+            if i % 2 == 1 {
+                expanded.push('%');
+            }
+            expanded.push_str(part);
+        }
+    }
+    expanded
+}
+
+/// Packages pinned with `choco pin`, which `choco upgrade all` doesn't upgrade.
+fn choco_pinned_packages(choco: &Path) -> Vec<String> {
+    match Command::new(choco).args(["pin", "list", "-r"]).output_checked_utf8() {
+        // Each line is `name|version`
+        Ok(output) => output
+            .stdout
+            .lines()
+            .filter_map(|line| line.split('|').next())
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(e) => {
+            debug!("Could not list pinned packages: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Print the packages of `choco outdated`, as a preview of what a dry run would upgrade.
+fn print_choco_outdated(choco: &Path) {
+    let output = match Command::new(choco).args(["outdated", "-r"]).output_checked_utf8() {
+        Ok(output) => output,
+        Err(e) => {
+            debug!("Could not list outdated packages: {e}");
+            return;
+        }
+    };
+
+    // Each line is `name|current version|available version|pinned`
+    let packages = output
+        .stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().split('|');
+            Some((fields.next()?.into(), fields.next()?.into(), fields.next()?.into()))
+        })
+        .collect();
+    print_would_upgrade(packages);
+}
+
+/// Print the packages of `winget upgrade`, as a preview of what a dry run would upgrade.
+fn print_winget_upgradable(winget: &Path) {
+    let output = match Command::new(winget).arg("upgrade").output_checked_utf8() {
+        Ok(output) => output,
+        Err(e) => {
+            debug!("Could not list upgradable packages: {e}");
+            return;
+        }
+    };
+
+    // Columns: Name, Id, Version, Available, Source
+    let packages = parse_table(&output.stdout)
+        .into_iter()
+        .filter(|row| row.len() >= 4 && !row[3].is_empty())
+        .map(|row| (row[0].clone(), row[2].clone(), row[3].clone()))
+        .collect();
+    print_would_upgrade(packages);
+}
+
+/// Print the apps of `scoop status`, as a preview of what a dry run would upgrade.
+fn print_scoop_outdated(scoop: &Path) {
+    let output = match Command::new(scoop).arg("status").output_checked_utf8() {
+        Ok(output) => output,
+        Err(e) => {
+            debug!("Could not list outdated apps: {e}");
+            return;
+        }
+    };
+
+    // Columns: Name, Installed Version, Latest Version, Missing Dependencies, Info
+    let packages = parse_table(&output.stdout)
+        .into_iter()
+        .filter(|row| row.len() >= 3 && !row[2].is_empty())
+        .map(|row| (row[0].clone(), row[1].clone(), row[2].clone()))
+        .collect();
+    print_would_upgrade(packages);
+}
+
+/// Print the `(name, current version, new version)` of each package to upgrade.
+fn print_would_upgrade(packages: Vec<(String, String, String)>) {
+    if packages.is_empty() {
+        println!("{}", t!("Nothing to upgrade"));
+        return;
+    }
+
+    println!("{}", t!("Would upgrade:"));
+    for (name, from, to) in packages {
+        println!("  {name} {from} -> {to}");
+    }
+}
+
+/// The rows of the first table in `output`, as printed by winget and Scoop: a
+/// header, a line of dashes, then a row per line until an empty line or a line
+/// that doesn't fit the columns.
+///
+/// Columns start where the runs of dashes start or, when the dashes are a single
+/// run, where the words of the header start.
+fn parse_table(output: &str) -> Vec<Vec<String>> {
+    // Progress spinners are redrawn with carriage returns
+    let lines: Vec<&str> = output
+        .lines()
+        .map(|line| line.rsplit('\r').next().unwrap_or(line).trim_end())
+        .collect();
+    let Some(dashes) = lines
+        .iter()
+        .position(|line| line.starts_with("---") && line.chars().all(|c| c == '-' || c == ' '))
+    else {
+        return Vec::new();
+    };
+    let Some(header) = dashes.checked_sub(1).map(|i| lines[i]) else {
+        return Vec::new();
+    };
+
+    let column_starts = |line: &str, marker: fn(char) -> bool| -> Vec<usize> {
+        let chars: Vec<char> = line.chars().collect();
+        (0..chars.len())
+            .filter(|&i| marker(chars[i]) && (i == 0 || chars[i - 1] == ' '))
+            .collect()
+    };
+    let mut starts = column_starts(lines[dashes], |c| c == '-');
+    if starts.len() == 1 {
+        starts = column_starts(header, |c| c != ' ');
+    }
+
+    lines[dashes + 1..]
+        .iter()
+        .map(|line| line.chars().collect::<Vec<char>>())
+        // A line like `2 upgrades available.`, running across the columns, ends the table too
+        .take_while(|chars| {
+            !chars.iter().all(|c| c.is_whitespace())
+                && starts
+                    .iter()
+                    .all(|&start| start == 0 || chars.get(start - 1).map_or(true, |&c| c == ' '))
+        })
+        .map(|chars| {
+            starts
+                .iter()
+                .enumerate()
+                .map(|(i, &start)| {
+                    let end = starts.get(i + 1).copied().unwrap_or(chars.len()).min(chars.len());
+                    chars[start.min(end)..end].iter().collect::<String>().trim().to_string()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Whether apps are installed in the global Scoop directory.
+fn has_global_scoop_apps() -> bool {
+    let global_dir = std::env::var_os("SCOOP_GLOBAL")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData\scoop"));
+
+    global_dir
+        .join("apps")
+        .read_dir()
+        .map(|mut apps| apps.next().is_some())
+        .unwrap_or(false)
+}
+
+/// Whether Topgrade runs with administrator privileges.
+pub fn is_elevated() -> bool {
+    // `net session` is only allowed for administrators
+    Command::new("net").arg("session").output_checked().is_ok()
+}
+
 pub fn update_wsl(ctx: &ExecutionContext) -> Result<()> {
     if !is_wsl_installed()? {
         return Err(SkipStep(t!("WSL not installed").to_string()).into());
@@ -253,3 +636,80 @@ pub fn insert_startup_scripts(git_repos: &mut RepoStep) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_winget_failed_packages() {
+        let output = "(1/3) Found 7-Zip [7zip.7zip] Version 24.08\n\
+            This application is licensed to you by its owner.\n\
+            Microsoft is not responsible for, nor does it grant any licenses to, third-party packages.\n\
+            Downloading https://www.7-zip.org/a/7z2408-x64.msi\n\
+            \r  \u{2592}\u{2592}\u{2592}\u{2592}  512 KB / 1.88 MB\r  \u{2588}\u{2588}\u{2588}\u{2588}  1.88 MB / 1.88 MB\n\
+            Successfully verified installer hash\n\
+            Starting package install...\n\
+            Installer failed with exit code: 1603\n\
+            (2/3) Found Git [Git.Git] Version 2.46.0\n\
+            Downloading https://github.com/git-for-windows/git/releases/download/v2.46.0.windows.1/Git-2.46.0-64-bit.exe\n\
+            \r  \u{2588}\u{2588}\u{2588}\u{2588}  65.0 MB / 65.0 MB\n\
+            Successfully verified installer hash\n\
+            Starting package install...\n\
+            Successfully installed\n\
+            (3/3) Found Zoom [Zoom.Zoom] Version 6.1.6\n\
+            Downloading https://zoom.us/client/6.1.6/ZoomInstallerFull.msi\n\
+            Installer hash does not match; this cannot be overridden when running as admin\n";
+
+        assert_eq!(
+            winget_failed_packages(output),
+            vec!["7-Zip [7zip.7zip]", "Zoom [Zoom.Zoom]"]
+        );
+    }
+
+    #[test]
+    fn test_winget_failed_packages_none() {
+        let output = "(1/1) Found Git [Git.Git] Version 2.46.0\n\
+            Starting package install...\n\
+            Successfully installed\n";
+
+        assert!(winget_failed_packages(output).is_empty());
+    }
+
+    #[test]
+    fn test_parse_table_winget() {
+        let output = "   - \r   \\ \r   | \r                                                  \r\
+            Name              Id        Version Available Source\n\
+            ------------------------------------------------------\n\
+            7-Zip 23.01 (x64) 7zip.7zip 23.01   24.08     winget\n\
+            Git               Git.Git   2.45.2  2.46.0    winget\n\
+            2 upgrades available.\n";
+
+        assert_eq!(
+            parse_table(output),
+            vec![
+                vec!["7-Zip 23.01 (x64)", "7zip.7zip", "23.01", "24.08", "winget"],
+                vec!["Git", "Git.Git", "2.45.2", "2.46.0", "winget"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_table_scoop() {
+        let output = "Scoop is up to date.\n\
+            \n\
+            Name   Installed Version Latest Version Missing Dependencies Info\n\
+            ----   ----------------- -------------- -------------------- ----\n\
+            git    2.45.2            2.46.0\n\
+            7zip   23.01             24.08                               Held package\n\
+            \n";
+
+        assert_eq!(
+            parse_table(output),
+            vec![
+                vec!["git", "2.45.2", "2.46.0", "", ""],
+                vec!["7zip", "23.01", "24.08", "", "Held package"],
+            ]
+        );
+    }
+}
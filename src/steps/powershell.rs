@@ -7,8 +7,9 @@ use color_eyre::eyre::Result;
 use rust_i18n::t;
 
 use crate::command::CommandExt;
+use crate::config::PowershellModuleScope;
 use crate::execution_context::ExecutionContext;
-use crate::terminal::{is_dumb, print_separator};
+use crate::terminal::{is_dumb, print_separator, print_warning};
 use crate::utils::{require_option, which, PathExt};
 use crate::Step;
 
@@ -67,7 +68,38 @@ impl Powershell {
 
         print_separator(t!("Powershell Modules Update"));
 
-        let mut cmd = vec!["Update-Module"];
+        let filter;
+        let scope;
+        let mut cmd = vec![];
+
+        if let Some(skip) = ctx.config().powershell_skip_modules().filter(|skip| !skip.is_empty()) {
+            let names: Vec<String> = skip
+                .iter()
+                .map(|module| format!("'{}'", module.replace('\'', "''")))
+                .collect();
+            filter = format!(
+                "Get-InstalledModule | Where-Object {{ $_.Name -notin @({}) }} |",
+                names.join(", ")
+            );
+            cmd.push(filter.as_str());
+        }
+
+        cmd.push("Update-Module");
+
+        if let Some(module_scope) = ctx.config().powershell_scope() {
+            if module_scope == PowershellModuleScope::AllUsers {
+                #[cfg(windows)]
+                let elevated = crate::steps::os::windows::is_elevated();
+                #[cfg(unix)]
+                let elevated = nix::unistd::Uid::effective().is_root();
+
+                if !elevated {
+                    print_warning(t!("Updating modules for all users requires an elevated shell"));
+                }
+            }
+            scope = format!("-Scope {module_scope}");
+            cmd.push(scope.as_str());
+        }
 
         if ctx.config().verbose() {
             cmd.push("-Verbose")
@@ -1,8 +1,15 @@
+use std::cell::Cell;
+
 use color_eyre::eyre::Result;
 use rust_i18n::t;
 
 use crate::{
-    command::CommandExt, error::SkipStep, execution_context::ExecutionContext, terminal::print_separator, utils,
+    command::CommandExt,
+    error::{RebootRequired, SkipStep, REBOOT_REQUIRED_EXIT_CODE, REPORT_REBOOT_ENV},
+    execution_context::ExecutionContext,
+    executor::RunType,
+    terminal::print_separator,
+    utils,
 };
 
 fn prepare_async_ssh_command(args: &mut Vec<&str>) {
@@ -13,7 +20,14 @@ fn prepare_async_ssh_command(args: &mut Vec<&str>) {
 pub fn ssh_step(ctx: &ExecutionContext, hostname: &str) -> Result<()> {
     let ssh = utils::require("ssh")?;
 
-    let topgrade = ctx.config().remote_topgrade_path();
+    // Previewing the remote hosts needs the remote Topgrade to run, even in a local dry run
+    let remote_dry_run = ctx.run_type().dry() || ctx.config().remote_dry_run_always();
+    let topgrade = if remote_dry_run {
+        format!("{} --dry-run", ctx.config().remote_topgrade_path())
+    } else {
+        ctx.config().remote_topgrade_path().to_string()
+    };
+    let topgrade = topgrade.as_str();
     let mut args = vec!["-t", hostname];
 
     if let Some(ssh_arguments) = ctx.config().ssh_arguments() {
@@ -45,11 +59,33 @@ pub fn ssh_step(ctx: &ExecutionContext, hostname: &str) -> Result<()> {
         }
 
         let env = format!("TOPGRADE_PREFIX={hostname}");
-        args.extend(["env", &env, "$SHELL", "-lc", topgrade]);
+        let report_reboot = format!("{REPORT_REBOOT_ENV}=1");
+        args.extend(["env", &env, &report_reboot, "$SHELL", "-lc", topgrade]);
 
-        print_separator(format!("Remote ({hostname})"));
+        if remote_dry_run && !ctx.run_type().dry() {
+            print_separator(format!("Remote ({hostname}) (dry run)"));
+        } else {
+            print_separator(format!("Remote ({hostname})"));
+        }
         println!("{}", t!("Connecting to {hostname}...", hostname = hostname));
 
-        ctx.run_type().execute(ssh).args(&args).status_checked()
+        let reboot_required = Cell::new(false);
+        let run_type = if remote_dry_run { RunType::Wet } else { ctx.run_type() };
+        run_type.execute(ssh).args(&args).status_checked_with(|status| {
+            if status.success() {
+                Ok(())
+            } else if status.code() == Some(REBOOT_REQUIRED_EXIT_CODE) {
+                reboot_required.set(true);
+                Ok(())
+            } else {
+                Err(())
+            }
+        })?;
+
+        if reboot_required.get() {
+            return Err(RebootRequired.into());
+        }
+
+        Ok(())
     }
 }
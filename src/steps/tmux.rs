@@ -32,7 +32,18 @@ pub fn run_tpm(ctx: &ExecutionContext) -> Result<()> {
 
     print_separator("tmux plugins");
 
-    ctx.run_type().execute(tpm).arg("all").status_checked()
+    // Inside a tmux session, run the update through the tmux server, like TPM's key
+    // binding does, so that the running session picks up the changes
+    match which("tmux") {
+        Some(tmux) if env::var_os("TMUX").is_some() => {
+            let update = format!("{} all", shell_words::quote(&tpm.to_string_lossy()));
+            ctx.run_type()
+                .execute(tmux)
+                .args(["run-shell", &update])
+                .status_checked()
+        }
+        _ => ctx.run_type().execute(tpm).arg("all").status_checked(),
+    }
 }
 
 struct Tmux {
@@ -149,10 +160,30 @@ pub fn run_in_tmux(config: TmuxConfig) -> Result<()> {
 
     let tmux = Tmux::new(config.args);
 
-    // Find an unused session and run `topgrade` in it with the current command's arguments.
-    let session_name = "topgrade";
     let window_name = "topgrade";
-    let session = tmux.new_unique_session(session_name, window_name, &command)?;
+    let session = match config.session_name {
+        // Reuse the named session if it exists, otherwise create it.
+        Some(session_name) => {
+            if tmux
+                .has_session(&session_name)
+                .context("Error determining if a tmux session exists")?
+            {
+                let indices = tmux.window_indices(&session_name)?;
+                let last_window = indices
+                    .iter()
+                    .last()
+                    .ok_or_else(|| eyre!("tmux session {session_name} has no windows"))?;
+                tmux.new_window(&session_name, &format!("{last_window}"), &command)
+                    .context("Error running Topgrade in tmux")?;
+            } else {
+                tmux.new_session(&session_name, window_name, &command)
+                    .context("Error running Topgrade in tmux")?;
+            }
+            session_name
+        }
+        // Find an unused session and run `topgrade` in it with the current command's arguments.
+        None => tmux.new_unique_session("topgrade", window_name, &command)?,
+    };
 
     let is_inside_tmux = env::var("TMUX").is_ok();
     let err = match config.session_mode {
@@ -44,6 +44,8 @@ struct Terminal {
     set_title: bool,
     display_time: bool,
     desktop_notification: bool,
+    /// Output held back until the step ends, see `--quiet`.
+    buffer: Option<Vec<u8>>,
 }
 
 impl Terminal {
@@ -58,6 +60,15 @@ impl Terminal {
             set_title: true,
             display_time: true,
             desktop_notification: false,
+            buffer: None,
+        }
+    }
+
+    /// Write to the terminal, or to the buffer while output is buffered.
+    fn write_fmt(&mut self, args: std::fmt::Arguments) -> io::Result<()> {
+        match &mut self.buffer {
+            Some(buffer) => buffer.write_fmt(args),
+            None => self.term.write_fmt(args),
         }
     }
 
@@ -113,27 +124,26 @@ impl Terminal {
 
         match self.width {
             Some(width) => {
-                self.term
-                    .write_fmt(format_args!(
-                        "{}\n",
-                        style(format_args!(
-                            "\n── {} {:─^border$}",
-                            message,
-                            "",
-                            border = max(
-                                2,
-                                min(80, width as usize)
-                                    .checked_sub(4)
-                                    .and_then(|e| e.checked_sub(message.len()))
-                                    .unwrap_or(0)
-                            )
-                        ))
-                        .bold()
+                self.write_fmt(format_args!(
+                    "{}\n",
+                    style(format_args!(
+                        "\n── {} {:─^border$}",
+                        message,
+                        "",
+                        border = max(
+                            2,
+                            min(80, width as usize)
+                                .checked_sub(4)
+                                .and_then(|e| e.checked_sub(message.len()))
+                                .unwrap_or(0)
+                        )
                     ))
-                    .ok();
+                    .bold()
+                ))
+                .ok();
             }
             None => {
-                self.term.write_fmt(format_args!("―― {message} ――\n")).ok();
+                self.write_fmt(format_args!("―― {message} ――\n")).ok();
             }
         }
     }
@@ -142,29 +152,25 @@ impl Terminal {
     fn print_error<P: AsRef<str>, Q: AsRef<str>>(&mut self, key: Q, message: P) {
         let key = key.as_ref();
         let message = message.as_ref();
-        self.term
-            .write_fmt(format_args!(
-                "{} {}",
-                style(format!("{}", t!("{key} failed:", key = key))).red().bold(),
-                message
-            ))
-            .ok();
+        self.write_fmt(format_args!(
+            "{} {}",
+            style(format!("{}", t!("{key} failed:", key = key))).red().bold(),
+            message
+        ))
+        .ok();
     }
 
     #[allow(dead_code)]
     fn print_warning<P: AsRef<str>>(&mut self, message: P) {
         let message = message.as_ref();
-        self.term
-            .write_fmt(format_args!("{}\n", style(message).yellow().bold()))
+        self.write_fmt(format_args!("{}\n", style(message).yellow().bold()))
             .ok();
     }
 
     #[allow(dead_code)]
     fn print_info<P: AsRef<str>>(&mut self, message: P) {
         let message = message.as_ref();
-        self.term
-            .write_fmt(format_args!("{}\n", style(message).blue().bold()))
-            .ok();
+        self.write_fmt(format_args!("{}\n", style(message).blue().bold())).ok();
     }
 
     fn print_result<P: AsRef<str>>(&mut self, key: P, result: &StepResult) {
@@ -176,6 +182,24 @@ impl Terminal {
                 key,
                 match result {
                     StepResult::Success => format!("{}", style(t!("OK")).bold().green()),
+                    StepResult::NoChanges => format!("{}", style(t!("NO CHANGES")).green()),
+                    StepResult::Updated(changes) => changes
+                        .iter()
+                        .fold(format!("{}", style(t!("OK")).bold().green()), |result, change| format!(
+                            "{result}\n    {change}"
+                        )),
+                    StepResult::RebootRequired => format!(
+                        "{} ({})",
+                        style(t!("OK")).bold().green(),
+                        style(t!("Reboot required")).bold().yellow()
+                    ),
+                    StepResult::PartialFailure(packages) => format!(
+                        "{} ({})",
+                        style(t!("OK")).bold().green(),
+                        style(t!("Failed to upgrade: {packages}", packages = packages.join(", ")))
+                            .bold()
+                            .yellow()
+                    ),
                     StepResult::Failure => format!("{}", style(t!("FAILED")).bold().red()),
                     StepResult::Ignored => format!("{}", style(t!("IGNORED")).bold().yellow()),
                     StepResult::Skipped(reason) => format!("{}: {}", style(t!("SKIPPED")).bold().blue(), reason),
@@ -319,6 +343,38 @@ pub fn notify_desktop<P: AsRef<str>>(message: P, timeout: Option<Duration>) {
     TERMINAL.lock().unwrap().notify_desktop(message, timeout)
 }
 
+/// Hold back the output of the separators, messages and commands until `take_buffer`.
+pub fn set_buffering(buffering: bool) {
+    TERMINAL.lock().unwrap().buffer = buffering.then(Vec::new);
+}
+
+pub fn is_buffering() -> bool {
+    TERMINAL.lock().unwrap().buffer.is_some()
+}
+
+/// Stop buffering, returning the buffered output.
+pub fn take_buffer() -> Vec<u8> {
+    TERMINAL.lock().unwrap().buffer.take().unwrap_or_default()
+}
+
+/// Write the output of a command to stdout or stderr, or to the buffer while output is buffered.
+pub fn write_output(data: &[u8], stderr: bool) -> io::Result<()> {
+    if let Some(buffer) = &mut TERMINAL.lock().unwrap().buffer {
+        buffer.extend_from_slice(data);
+        return Ok(());
+    }
+
+    if stderr {
+        let mut stderr = io::stderr();
+        stderr.write_all(data)?;
+        stderr.flush()
+    } else {
+        let mut stdout = io::stdout();
+        stdout.write_all(data)?;
+        stdout.flush()
+    }
+}
+
 pub fn display_time(display_time: bool) {
     TERMINAL.lock().unwrap().display_time(display_time);
 }
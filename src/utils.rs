@@ -13,6 +13,7 @@ use tracing_subscriber::reload::{Handle, Layer};
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{fmt, Registry};
 use tracing_subscriber::{registry, EnvFilter};
+use walkdir::WalkDir;
 
 use crate::command::CommandExt;
 use crate::config::DEFAULT_LOG_LEVEL;
@@ -240,6 +241,28 @@ pub fn check_is_python_2_or_shim(python: PathBuf) -> Result<PathBuf> {
     Ok(python)
 }
 
+/// The total size of the files in `dir`, 0 if it doesn't exist.
+pub fn dir_size(dir: &Path) -> u64 {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Print the space freed by a cache cleanup.
+pub fn print_reclaimed(bytes: u64) {
+    println!(
+        "{}",
+        t!(
+            "Reclaimed {size} MiB",
+            size = format!("{:.1}", bytes as f64 / (1024.0 * 1024.0))
+        )
+    );
+}
+
 /// Set up the tracing logger
 ///
 /// # Return value